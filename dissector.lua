@@ -29,6 +29,9 @@ ilnk_proto.fields.latency_usec 			= ProtoField.int32("SnapCast.latency_usec", "L
 -- type 5 (Hello)
 ilnk_proto.fields.client_hello 			= ProtoField.string("SnapCast.client_hello", "Client Hello")
 
+-- type 6 (StreamTags)
+ilnk_proto.fields.stream_tags 			= ProtoField.string("SnapCast.stream_tags", "Stream Tags")
+
 partial_bufs = {}
 pending_bytes = 0
 
@@ -99,6 +102,9 @@ function ilnk_proto.dissector(buffer, pinfo, tree)
 	elseif typename == "ServerSettings" then
 		local strsize = buffer(26, 4):le_uint()
 		subtree:add_le(ilnk_proto.fields.server_settings, buffer(30, strsize))
+	elseif typename == "StreamTags" then
+		local strsize = buffer(26, 4):le_uint()
+		subtree:add_le(ilnk_proto.fields.stream_tags, buffer(30, strsize))
 	elseif typename == "WireChunk" then
 
 		subtree:add_le(ilnk_proto.fields.play_at_s, buffer(26, 4):le_int())
@@ -0,0 +1,39 @@
+#![no_main]
+//! Drives a `ClientMachine` with arbitrary event sequences, as a hostile server
+//! (or a buggy driver) could: headers and packets of any length, in any order,
+//! interleaved with transmit polls at arbitrary times.
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use snapcast_client::client::{ClientMachine, MAX_TRANSMIT};
+use snapcast_client::framing::Event;
+use snapcast_client::proto::Base;
+
+#[derive(Arbitrary, Debug)]
+enum Step<'a> {
+    Header(&'a [u8]),
+    Packet(&'a [u8]),
+    /// A whole wire message, split into header and packet the way the driver would.
+    Frame(&'a [u8]),
+    Transmit,
+}
+
+fuzz_target!(|steps: Vec<(Step<'_>, i64)>| {
+    let mut m = ClientMachine::new();
+    let mut tx = [0u8; MAX_TRANSMIT];
+    for (step, now_us) in steps {
+        // errors are fine, panics are not; keep going to reach odd states
+        match step {
+            Step::Header(b) => drop(m.handle_event(Event::HeaderReceived(b), now_us)),
+            Step::Packet(b) => drop(m.handle_event(Event::PacketReceived(b), now_us)),
+            Step::Frame(b) => {
+                let (hdr, payload) = b.split_at(b.len().min(Base::BASE_SIZE));
+                if m.handle_event(Event::HeaderReceived(hdr), now_us).is_ok() {
+                    drop(m.handle_event(Event::PacketReceived(payload), now_us));
+                }
+            }
+            Step::Transmit => while m.poll_transmit(now_us, &mut tx).is_some() {},
+        }
+        let _ = m.clock_offset();
+    }
+});
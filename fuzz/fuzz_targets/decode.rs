@@ -0,0 +1,15 @@
+#![no_main]
+//! Parses one raw wire message with both the server- and client-side decoders.
+//! Seeded from the `proto::tests` vectors; see the README.
+
+use libfuzzer_sys::fuzz_target;
+use snapcast_client::proto::Base;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(base) = Base::try_from(data) else {
+        return;
+    };
+    let payload = &data[Base::BASE_SIZE..];
+    let _ = base.decode(payload);
+    let _ = base.decode_c(payload);
+});
@@ -0,0 +1,14 @@
+#![no_main]
+//! Decodes an arbitrary FLAC frame payload, as carried by a WireChunk, for a
+//! stream of the sample size in the first byte.
+
+use libfuzzer_sys::fuzz_target;
+use snapcast_client::decoder::{Decode, FlacDecoder};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&bits, data)) = data.split_first() else {
+        return;
+    };
+    let mut out = vec![0i32; 4700];
+    let _ = FlacDecoder::new(bits as u16).decode_sample(data, &mut out);
+});
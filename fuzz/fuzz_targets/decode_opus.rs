@@ -0,0 +1,27 @@
+#![no_main]
+//! Decodes a sequence of arbitrary Opus packets through one decoder instance,
+//! so state carried between packets is exercised too. Empty packets stand for
+//! lost ones and go through concealment.
+
+use libfuzzer_sys::fuzz_target;
+use snapcast_client::decoder::{Decode, Decoder};
+use snapcast_client::proto::OpusMetadata;
+
+fuzz_target!(|packets: Vec<&[u8]>| {
+    let cfg = OpusMetadata {
+        sample_rate: 48_000,
+        bit_depth: 16,
+        channel_count: 2,
+    };
+    let Ok(mut dec) = Decoder::new_opus(&cfg) else {
+        return;
+    };
+    // 120ms, the longest Opus frame, of 48kHz stereo
+    let mut out = vec![0i32; 5760 * 2];
+    for p in packets {
+        let _ = match p {
+            [] => dec.conceal(960, &mut out),
+            p => dec.decode_sample(p, &mut out),
+        };
+    }
+});
@@ -0,0 +1,176 @@
+#![no_main]
+//! Every message type must decode back to exactly what `as_buf` encoded.
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use snapcast_client::proto::{
+    Base, ClientHello, ClientInfo, ClientMessage, CodecHeader, CodecMetadata, OpusMetadata,
+    ServerMessage, ServerSettings, StreamTags, Time, TimeVal, WireChunk,
+};
+
+#[derive(Arbitrary, Debug)]
+enum Msg<'a> {
+    ServerSettings {
+        buffer_ms: u32,
+        latency: u32,
+        muted: bool,
+        volume: u8,
+    },
+    OpusHeader {
+        sample_rate: u32,
+        bit_depth: u16,
+        channel_count: u16,
+    },
+    WireChunk {
+        timestamp: (i32, i32),
+        payload: &'a [u8],
+    },
+    Time {
+        id: u16,
+        refers_to: u16,
+        latency: (i32, i32),
+    },
+    StreamTags {
+        title: Option<String>,
+        artist: Option<String>,
+        album: Option<String>,
+        art_url: Option<String>,
+    },
+    Hello {
+        strings: [&'a str; 7],
+        instance: u8,
+        protocol: u8,
+    },
+    ClientInfo {
+        volume: u8,
+        muted: bool,
+    },
+}
+
+fn tv((sec, usec): (i32, i32)) -> TimeVal {
+    TimeVal { sec, usec }
+}
+
+fn split(buf: &[u8]) -> Option<(Base, &[u8])> {
+    // only a payload over Base::MAX_PAYLOAD may be refused
+    if buf.len() > Base::BASE_SIZE + Base::MAX_PAYLOAD {
+        return None;
+    }
+    let base = Base::try_from(buf).expect("our own header parses");
+    Some((base, &buf[Base::BASE_SIZE..]))
+}
+
+fn server_roundtrip(buf: &[u8], expected: ServerMessage) {
+    if let Some((base, payload)) = split(buf) {
+        assert_eq!(base.decode(payload).unwrap(), expected);
+    }
+}
+
+fn client_roundtrip(buf: &[u8], expected: ClientMessage) {
+    if let Some((base, payload)) = split(buf) {
+        assert_eq!(base.decode_c(payload).unwrap(), expected);
+    }
+}
+
+fuzz_target!(|msg: Msg<'_>| {
+    let now = TimeVal { sec: 1, usec: 2 };
+    match msg {
+        Msg::ServerSettings {
+            buffer_ms,
+            latency,
+            muted,
+            volume,
+        } => {
+            let s = ServerSettings {
+                bufferMs: buffer_ms,
+                latency,
+                muted,
+                volume,
+            };
+            server_roundtrip(&s.as_buf(0, now), ServerMessage::ServerSettings(s));
+        }
+        Msg::OpusHeader {
+            sample_rate,
+            bit_depth,
+            channel_count,
+        } => {
+            let ch = CodecHeader {
+                codec: "opus",
+                metadata: CodecMetadata::Opus(OpusMetadata {
+                    sample_rate,
+                    bit_depth,
+                    channel_count,
+                }),
+            };
+            server_roundtrip(&ch.as_buf(0, now), ServerMessage::CodecHeader(ch));
+        }
+        Msg::WireChunk { timestamp, payload } => {
+            let wc = WireChunk {
+                timestamp: tv(timestamp),
+                payload,
+            };
+            server_roundtrip(&wc.as_buf(0, now), ServerMessage::WireChunk(wc));
+        }
+        Msg::Time {
+            id,
+            refers_to,
+            latency,
+        } => {
+            let buf = Time::as_buf(id, refers_to, now, now, tv(latency));
+            let (base, payload) = split(&buf).unwrap();
+            match base.decode(payload).unwrap() {
+                ServerMessage::Time(t) => assert_eq!(t.latency(), tv(latency)),
+                other => panic!("expected Time, got {other:?}"),
+            }
+            match base.decode_c(payload).unwrap() {
+                ClientMessage::Time(t) => assert_eq!(t.latency(), tv(latency)),
+                other => panic!("expected Time, got {other:?}"),
+            }
+        }
+        Msg::StreamTags {
+            title,
+            artist,
+            album,
+            art_url,
+        } => {
+            let tags = StreamTags {
+                title,
+                artist,
+                album,
+                art_url,
+            };
+            server_roundtrip(&tags.as_buf(0, now), ServerMessage::StreamTags(tags));
+        }
+        Msg::Hello {
+            strings,
+            instance,
+            protocol,
+        } => {
+            // Hello borrows its strings from the packet, so ones that JSON has
+            // to escape cannot be handed back without copying
+            if strings
+                .iter()
+                .any(|s| s.chars().any(|c| c == '"' || c == '\\' || c.is_control()))
+            {
+                return;
+            }
+            let [mac, host, version, name, os, arch, id] = strings;
+            let hello = ClientHello {
+                MAC: mac,
+                HostName: host,
+                Version: version,
+                ClientName: name,
+                OS: os,
+                Arch: arch,
+                Instance: instance,
+                ID: id,
+                SnapStreamProtocolVersion: protocol,
+            };
+            client_roundtrip(&hello.as_buf(), ClientMessage::Hello(hello));
+        }
+        Msg::ClientInfo { volume, muted } => {
+            let info = ClientInfo { volume, muted };
+            client_roundtrip(&info.as_buf(0, now), ClientMessage::ClientInfo(info));
+        }
+    }
+});
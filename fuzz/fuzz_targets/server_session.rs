@@ -0,0 +1,35 @@
+#![no_main]
+//! Drives a `ServerSession` with arbitrary event sequences, as a hostile client
+//! could: headers and packets of any length, in any order, at arbitrary times.
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use snapcast_client::framing::Event;
+use snapcast_client::proto::{Base, TimeVal};
+use snapcast_client::server::ServerSession;
+
+#[derive(Arbitrary, Debug)]
+enum Step<'a> {
+    Header(&'a [u8]),
+    Packet(&'a [u8]),
+    /// A whole wire message, split into header and packet the way the runtime would.
+    Frame(&'a [u8]),
+}
+
+fuzz_target!(|steps: Vec<(Step<'_>, i32, i32)>| {
+    let mut s = ServerSession::new();
+    for (step, sec, usec) in steps {
+        let now = TimeVal { sec, usec };
+        // errors are fine, panics are not; keep going to reach odd states
+        match step {
+            Step::Header(b) => drop(s.handle_event(Event::HeaderReceived(b), now)),
+            Step::Packet(b) => drop(s.handle_event(Event::PacketReceived(b), now)),
+            Step::Frame(b) => {
+                let (hdr, payload) = b.split_at(b.len().min(Base::BASE_SIZE));
+                if s.handle_event(Event::HeaderReceived(hdr), now).is_ok() {
+                    drop(s.handle_event(Event::PacketReceived(payload), now));
+                }
+            }
+        }
+    }
+});
@@ -29,6 +29,11 @@ pub struct Config {
     #[arg(long, default_value_t = 96_000)]
     pub opus_bitrate: i32,
 
+    /// Expected packet loss in percent; above 0, every opus frame carries a
+    /// low-bitrate copy of the previous one (in-band FEC) sized for it.
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub opus_fec_loss: u8,
+
     /// Name advertised to Spotify Connect.
     #[arg(long, default_value = "snapcast-rs")]
     pub device_name: String,
@@ -18,11 +18,12 @@ fn main() -> anyhow::Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let config = Config::parse();
     log::info!(
-        "starting: bind={} buffer_ms={} chunk_ms={} opus_bitrate={} device={:?} cache={:?} source={:?}",
+        "starting: bind={} buffer_ms={} chunk_ms={} opus_bitrate={} opus_fec_loss={} device={:?} cache={:?} source={:?}",
         config.bind,
         config.buffer_ms,
         config.chunk_ms,
         config.opus_bitrate,
+        config.opus_fec_loss,
         config.device_name,
         config.cache_dir,
         config.source,
@@ -42,8 +43,13 @@ fn main() -> anyhow::Result<()> {
 
     match config.source {
         Source::Sine => {
-            let pipeline =
-                Pipeline::new(clock, registry.clone(), config.chunk_ms, config.opus_bitrate)?;
+            let pipeline = Pipeline::new(
+                clock,
+                registry.clone(),
+                config.chunk_ms,
+                config.opus_bitrate,
+                config.opus_fec_loss,
+            )?;
             source::run_sine(pipeline)?;
         }
         Source::Spotify => {
@@ -9,7 +9,8 @@ use mdns_sd::{ServiceDaemon, ServiceInfo};
 
 use snapcast_client::framing::{Action, Event};
 use snapcast_client::proto::{
-    Base, CodecHeader, CodecMetadata, OpusMetadata, ServerSettings, Time, TimeVal, WireChunk,
+    Base, ClientInfo, CodecHeader, CodecMetadata, OpusMetadata, ServerSettings, Time, TimeVal,
+    WireChunk,
 };
 use snapcast_client::server::{ServerSession, SessionOutput};
 
@@ -69,6 +70,7 @@ struct ClientHandle {
 struct Inner {
     clients: HashMap<ClientId, ClientHandle>,
     volume: u8,
+    muted: bool,
     next_id: ClientId,
 }
 
@@ -83,6 +85,7 @@ impl Registry {
             inner: Mutex::new(Inner {
                 clients: HashMap::new(),
                 volume: initial_volume,
+                muted: false,
                 next_id: 0,
             }),
             buffer_ms,
@@ -91,10 +94,14 @@ impl Registry {
 
     pub fn current_settings(&self) -> ServerSettings {
         let inner = self.inner.lock().unwrap();
+        self.settings(&inner)
+    }
+
+    fn settings(&self, inner: &Inner) -> ServerSettings {
         ServerSettings {
             bufferMs: self.buffer_ms,
             latency: 0,
-            muted: false,
+            muted: inner.muted,
             volume: inner.volume,
         }
     }
@@ -159,15 +166,23 @@ impl Registry {
     /// Update the stored volume and push fresh settings to every client. Control
     /// messages use a blocking send outside the lock, so they are never dropped.
     pub fn broadcast_settings(&self, volume: u8) {
+        self.update_settings(|inner| inner.volume = volume);
+    }
+
+    /// Adopt a volume/mute change reported by one client (e.g. its hardware
+    /// knob) as the shared state, and push it to every client.
+    pub fn apply_client_info(&self, info: ClientInfo) {
+        self.update_settings(|inner| {
+            inner.volume = info.volume;
+            inner.muted = info.muted;
+        });
+    }
+
+    fn update_settings(&self, update: impl FnOnce(&mut Inner)) {
         let (settings, txs) = {
             let mut inner = self.inner.lock().unwrap();
-            inner.volume = volume;
-            let settings = ServerSettings {
-                bufferMs: self.buffer_ms,
-                latency: 0,
-                muted: false,
-                volume,
-            };
+            update(&mut inner);
+            let settings = self.settings(&inner);
             let txs: Vec<SyncSender<Outbound>> =
                 inner.clients.values().map(|h| h.tx.clone()).collect();
             (settings, txs)
@@ -297,6 +312,14 @@ fn reader_loop(
                             })?;
                         }
                     }
+                    SessionOutput::ClientInfo(info) => {
+                        log::info!(
+                            "client {my_id:?} reports volume {} muted {}",
+                            info.volume,
+                            info.muted
+                        );
+                        registry.apply_client_info(info);
+                    }
                 }
             }
         }
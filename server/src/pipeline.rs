@@ -46,6 +46,7 @@ impl Pipeline {
         registry: Arc<Registry>,
         chunk_ms: u32,
         opus_bitrate: i32,
+        opus_fec_loss: u8,
     ) -> anyhow::Result<Pipeline> {
         // opus only encodes 2.5/5/10/20/40/60ms frames; at 48k those are the only
         // chunk sizes it will accept, so reject anything else up front
@@ -64,6 +65,10 @@ impl Pipeline {
             Application::Audio,
         ))?;
         opus(encoder.set_bitrate(Bitrate::BitsPerSecond(opus_bitrate)))?;
+        if opus_fec_loss > 0 {
+            opus(encoder.set_inband_fec(true))?;
+            opus(encoder.set_packet_loss_perc(opus_fec_loss))?;
+        }
 
         Ok(Pipeline {
             resampler,
@@ -108,10 +108,11 @@ async fn run_session(
     let reg = registry.clone();
     let chunk_ms = config.chunk_ms;
     let bitrate = config.opus_bitrate;
+    let fec_loss = config.opus_fec_loss;
     let sink_tx = source_tx.clone();
     // NoOpVolume keeps PCM full-scale; volume is applied downstream at each DAC
     let player = Player::new(player_config.clone(), session.clone(), Box::new(NoOpVolume), move || {
-        let pipeline = Pipeline::new(clk, reg, chunk_ms, bitrate).expect("failed to build pipeline");
+        let pipeline = Pipeline::new(clk, reg, chunk_ms, bitrate, fec_loss).expect("failed to build pipeline");
         Box::new(PipelineSink::new(pipeline, sink_tx)) as Box<dyn Sink>
     });
 
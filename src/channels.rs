@@ -0,0 +1,307 @@
+use std::str::FromStr;
+
+/// Which of a stream's channels reach the player, and how. Streams are taken
+/// in WAV/FLAC channel order: left, right, centre, LFE, then the surrounds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelMap {
+    /// Every channel as it is.
+    #[default]
+    Auto,
+    /// Everything mixed down to one channel.
+    Mono,
+    /// Surround mixed down to two channels; mono copied to both.
+    Stereo,
+    /// The left channel as mono, for one speaker of a stereo pair; a mono
+    /// stream plays as it is.
+    Left,
+    /// The right channel as mono; a mono stream plays as it is.
+    Right,
+    /// Only this channel (0 is left, 1 right) as mono.
+    Channel(usize),
+}
+
+impl ChannelMap {
+    /// How many channels a `from` channel stream has once mapped.
+    pub fn channels(&self, from: usize) -> usize {
+        match self {
+            ChannelMap::Auto => from,
+            ChannelMap::Mono | ChannelMap::Left | ChannelMap::Right | ChannelMap::Channel(_) => 1,
+            ChannelMap::Stereo => 2,
+        }
+    }
+}
+
+impl FromStr for ChannelMap {
+    type Err = String;
+    /// `auto`, `mono`, `stereo`, `left`, `right` or `channel:<n>`, counting
+    /// from 0.
+    fn from_str(s: &str) -> Result<ChannelMap, String> {
+        Ok(match s {
+            "auto" => ChannelMap::Auto,
+            "mono" => ChannelMap::Mono,
+            "stereo" => ChannelMap::Stereo,
+            "left" => ChannelMap::Left,
+            "right" => ChannelMap::Right,
+            _ => {
+                let n = s.strip_prefix("channel:").and_then(|n| n.parse().ok());
+                ChannelMap::Channel(n.ok_or_else(|| {
+                    format!(
+                        "invalid channel map {s:?}: expected auto, mono, stereo, left, right or channel:<n>"
+                    )
+                })?)
+            }
+        })
+    }
+}
+
+/// Weights are 16.16 fixed point.
+const UNIT: i64 = 1 << 16;
+/// -3dB, for a channel shared between both sides or folded into one.
+const HALF_POWER: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// How much of each input channel of a `from` channel stream goes to the
+/// left and to the right in a stereo mixdown, before normalizing. LFE is
+/// dropped; layouts without a standard order go alternately left and right.
+fn stereo_weights(from: usize) -> Vec<(f32, f32)> {
+    const L: (f32, f32) = (1.0, 0.0);
+    const R: (f32, f32) = (0.0, 1.0);
+    const C: (f32, f32) = (HALF_POWER, HALF_POWER);
+    const LFE: (f32, f32) = (0.0, 0.0);
+    const SL: (f32, f32) = (HALF_POWER, 0.0);
+    const SR: (f32, f32) = (0.0, HALF_POWER);
+    match from {
+        1 => vec![(1.0, 1.0)],
+        2 => vec![L, R],
+        3 => vec![L, R, C],
+        4 => vec![L, R, SL, SR],
+        5 => vec![L, R, C, SL, SR],
+        6 => vec![L, R, C, LFE, SL, SR],
+        7 => vec![L, R, C, LFE, (0.5, 0.5), SL, SR],
+        8 => vec![L, R, C, LFE, SL, SR, SL, SR],
+        _ => (0..from).map(|c| if c % 2 == 0 { L } else { R }).collect(),
+    }
+}
+
+/// Applies a [`ChannelMap`] to interleaved full-scale samples, in place.
+pub struct ChannelMapper {
+    from: usize,
+    to: usize,
+    /// `to` rows of `from` weights; empty when frames pass through as they are.
+    matrix: Vec<i64>,
+    frame: Vec<i32>,
+}
+
+impl ChannelMapper {
+    /// Map a `from` channel stream; fails if `map` picks a channel the stream
+    /// does not have.
+    pub fn new(map: ChannelMap, from: usize) -> anyhow::Result<ChannelMapper> {
+        anyhow::ensure!(from > 0, "a stream needs at least one channel");
+        let to = map.channels(from);
+        let mut rows: Vec<Vec<f32>> = match map {
+            ChannelMap::Auto => vec![],
+            ChannelMap::Left | ChannelMap::Right | ChannelMap::Channel(_) => {
+                let c = match map {
+                    ChannelMap::Channel(c) => c,
+                    // a mono stream's only channel is either side
+                    ChannelMap::Right if from > 1 => 1,
+                    _ => 0,
+                };
+                anyhow::ensure!(
+                    c < from,
+                    "cannot pick channel {c} of a {from} channel stream"
+                );
+                let mut row = vec![0.0; from];
+                row[c] = 1.0;
+                vec![row]
+            }
+            ChannelMap::Stereo | ChannelMap::Mono => {
+                let w = stereo_weights(from);
+                let sum = |side: fn(&(f32, f32)) -> f32| w.iter().map(side).sum::<f32>();
+                let (left, right) = (sum(|w| w.0), sum(|w| w.1));
+                let left: Vec<f32> = w.iter().map(|w| w.0 / left).collect();
+                let right: Vec<f32> = w.iter().map(|w| w.1 / right).collect();
+                if map == ChannelMap::Stereo {
+                    vec![left, right]
+                } else {
+                    vec![left
+                        .iter()
+                        .zip(&right)
+                        .map(|(l, r)| (l + r) / 2.0)
+                        .collect()]
+                }
+            }
+        };
+        // maps that change nothing (stereo of stereo, mono of mono) pass through
+        let unit = |r: usize, c: usize| (r == c) as u8 as f32;
+        if from == to
+            && rows
+                .iter()
+                .enumerate()
+                .all(|(r, row)| row.iter().enumerate().all(|(c, &w)| w == unit(r, c)))
+        {
+            rows.clear();
+        }
+        Ok(ChannelMapper {
+            from,
+            to,
+            matrix: rows
+                .iter()
+                .flatten()
+                .map(|w| (w * UNIT as f32).round() as i64)
+                .collect(),
+            frame: vec![0; from],
+        })
+    }
+
+    /// Channels coming out.
+    pub fn channels(&self) -> usize {
+        self.to
+    }
+
+    /// Map the first `n` samples of `buf`; returns the new sample count. `buf`
+    /// must have room for the mapped frames when they are wider.
+    pub fn map(&mut self, buf: &mut [i32], n: usize) -> usize {
+        if self.matrix.is_empty() {
+            return n;
+        }
+        let (from, to) = (self.from, self.to);
+        let frames = n / from;
+        assert!(
+            frames * to <= buf.len(),
+            "mapped audio overflows the buffer"
+        );
+        // narrowing goes front to back, widening back to front, so no frame is
+        // overwritten before it is read
+        let mut map_frame = |f: usize| {
+            self.frame.copy_from_slice(&buf[f * from..(f + 1) * from]);
+            for (c, row) in self.matrix.chunks(from).enumerate() {
+                let mixed: i64 = row
+                    .iter()
+                    .zip(&self.frame)
+                    .map(|(w, &s)| w * s as i64)
+                    .sum();
+                buf[f * to + c] = (mixed / UNIT).clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+            }
+        };
+        if to <= from {
+            (0..frames).for_each(&mut map_frame);
+        } else {
+            (0..frames).rev().for_each(&mut map_frame);
+        }
+        frames * to
+    }
+}
+
+/// Spread the first `n` interleaved samples of a `from`-channel stream in
+/// `buf` across `to` channels, in place; returns the new sample count. Mono
+/// is copied to every output channel, wider streams keep their channels in
+/// order and leave the extra outputs silent.
+pub fn upmix(buf: &mut [i32], n: usize, from: usize, to: usize) -> usize {
+    assert!(
+        from > 0 && from <= to,
+        "cannot upmix {from} to {to} channels"
+    );
+    let frames = n / from;
+    assert!(
+        frames * to <= buf.len(),
+        "upmixed audio overflows the buffer"
+    );
+    if from == to {
+        return n;
+    }
+    // back to front, so no frame is overwritten before it is moved
+    for f in (0..frames).rev() {
+        for c in (0..to).rev() {
+            buf[f * to + c] = match (from, c < from) {
+                (1, _) => buf[f],
+                (_, true) => buf[f * from + c],
+                (_, false) => 0,
+            };
+        }
+    }
+    frames * to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mono_goes_to_every_channel() {
+        let mut buf = [1, 2, 3, 0, 0, 0];
+        assert_eq!(upmix(&mut buf, 3, 1, 2), 6);
+        assert_eq!(buf, [1, 1, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn extra_channels_are_silent() {
+        let mut buf = [1, 2, 3, 4, 9, 9, 9, 9];
+        assert_eq!(upmix(&mut buf, 4, 2, 4), 8);
+        assert_eq!(buf, [1, 2, 0, 0, 3, 4, 0, 0]);
+        assert_eq!(upmix(&mut buf, 8, 4, 4), 8);
+    }
+
+    #[test]
+    fn channel_maps_parse() {
+        assert_eq!("right".parse(), Ok(ChannelMap::Right));
+        assert_eq!("channel:4".parse(), Ok(ChannelMap::Channel(4)));
+        assert_eq!("stereo".parse(), Ok(ChannelMap::Stereo));
+        assert!("channel:".parse::<ChannelMap>().is_err());
+        assert!("surround".parse::<ChannelMap>().is_err());
+    }
+
+    #[test]
+    fn one_channel_of_a_stereo_pair() {
+        let mut buf = [1, 2, 3, 4, 5, 6];
+        let mut m = ChannelMapper::new(ChannelMap::Channel(1), 2).unwrap();
+        assert_eq!(m.map(&mut buf, 6), 3);
+        assert_eq!(buf[..3], [2, 4, 6]);
+        assert!(ChannelMapper::new(ChannelMap::Channel(1), 1).is_err());
+
+        // a mono stream plays on either side
+        let mut buf = [1, 2];
+        let mut m = ChannelMapper::new(ChannelMap::Right, 1).unwrap();
+        assert_eq!(m.map(&mut buf, 2), 2);
+        assert_eq!(buf, [1, 2]);
+        let mut m = ChannelMapper::new(ChannelMap::Right, 2).unwrap();
+        assert_eq!(m.map(&mut buf, 2), 1);
+        assert_eq!(buf[0], 2);
+    }
+
+    #[test]
+    fn downmixes_keep_full_scale() {
+        // stereo to mono averages
+        let mut buf = [1000, 3000, -1000, -1000];
+        let mut m = ChannelMapper::new(ChannelMap::Mono, 2).unwrap();
+        assert_eq!(m.map(&mut buf, 4), 2);
+        assert_eq!(buf[..2], [2000, -1000]);
+
+        // 5.1 at full scale on every channel stays (just) in range, and the
+        // LFE is left out
+        let max = i32::MAX;
+        let mut buf = [max, max, max, max, max, max, 0, 0, 0, 1 << 24, 0, 0];
+        let mut m = ChannelMapper::new(ChannelMap::Stereo, 6).unwrap();
+        assert_eq!(m.map(&mut buf, 12), 4);
+        assert!(buf[..2].iter().all(|&s| s > max - (1 << 16)), "{buf:?}");
+        assert_eq!(buf[2..4], [0, 0]);
+
+        // the centre goes to both sides
+        let mut buf = [0, 0, 1 << 20];
+        let mut m = ChannelMapper::new(ChannelMap::Stereo, 3).unwrap();
+        assert_eq!(m.map(&mut buf, 3), 2);
+        assert_eq!(buf[0], buf[1]);
+        assert!(buf[0] > 0);
+    }
+
+    #[test]
+    fn mono_is_copied_to_stereo() {
+        let mut buf = [1, 2, 0, 0];
+        let mut m = ChannelMapper::new(ChannelMap::Stereo, 1).unwrap();
+        assert_eq!(m.map(&mut buf, 2), 4);
+        assert_eq!(buf, [1, 1, 2, 2]);
+        // maps that change nothing leave the samples alone
+        let mut m = ChannelMapper::new(ChannelMap::Stereo, 2).unwrap();
+        assert_eq!(m.map(&mut buf, 4), 4);
+        assert_eq!(buf, [1, 1, 2, 2]);
+    }
+}
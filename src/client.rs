@@ -1,12 +1,15 @@
+use crate::clock::ClockModel;
+use crate::identity;
 use crate::proto::{
-    Base, ClientHello, CodecHeader, ServerMessage, ServerSettings, Time, TimeVal, WireChunk,
+    Base, ClientHello, ClientInfo, CodecHeader, CodecMetadata, ServerMessage, ServerSettings,
+    StreamTags, Time, TimeVal, WireChunk,
 };
 pub use crate::framing::{Action, Event};
-use crate::framing::Framing;
+use crate::framing::{Framing, RxBuf};
 use anyhow::Context;
 use circular_buffer::CircularBuffer;
 use std::io::prelude::*;
-use std::net::{TcpStream, ToSocketAddrs};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::time::{Duration, Instant};
 
 pub enum Message<'a> {
@@ -16,26 +19,88 @@ pub enum Message<'a> {
     WireChunk(WireChunk<'a>, TimeVal),
     ServerSettings(ServerSettings),
     CodecHeader(CodecHeader<'a>),
+    /// Now-playing metadata: title, artist, album and art URL
+    StreamTags(StreamTags),
 }
 
+/// A [`Message`] that owns its payload, for callers that keep messages past the
+/// next read (queues, channels, async streams).
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedMessage {
+    Expired(TimeVal),
+    WireChunk {
+        timestamp: TimeVal,
+        payload: Vec<u8>,
+        audible_at: TimeVal,
+    },
+    ServerSettings(ServerSettings),
+    CodecHeader {
+        codec: String,
+        metadata: CodecMetadata,
+    },
+    StreamTags(StreamTags),
+}
+
+impl Message<'_> {
+    /// Copy out the borrowed payload; `None` for [`Message::Nothing`].
+    pub fn into_owned(self) -> Option<OwnedMessage> {
+        Some(match self {
+            Message::Nothing => return None,
+            Message::Expired(late) => OwnedMessage::Expired(late),
+            Message::WireChunk(wc, audible_at) => OwnedMessage::WireChunk {
+                timestamp: wc.timestamp,
+                payload: wc.payload.to_vec(),
+                audible_at,
+            },
+            Message::ServerSettings(s) => OwnedMessage::ServerSettings(s),
+            Message::CodecHeader(ch) => OwnedMessage::CodecHeader {
+                codec: ch.codec.to_string(),
+                metadata: ch.metadata,
+            },
+            Message::StreamTags(t) => OwnedMessage::StreamTags(t),
+        })
+    }
+}
+
+/// Time exchanges needed before playback starts.
 const LATENCY_SAMPLES: usize = 20;
 
+/// Unanswered Time requests remembered; older ones are presumed lost.
+const OUTSTANDING: usize = 8;
+
+/// Round trips the minimum RTT is taken over.
+const RTT_WINDOW: usize = 32;
+
+/// A round trip may exceed the minimum by the minimum itself, but never less
+/// than this, before its sample is discarded.
+const MIN_RTT_SLACK_US: i64 = 1_000;
+
+/// Size of the buffer [`ClientMachine::poll_transmit`] needs to fit any message.
+pub const MAX_TRANSMIT: usize = if Time::WIRE_SIZE > ClientInfo::MAX_WIRE_SIZE {
+    Time::WIRE_SIZE
+} else {
+    ClientInfo::MAX_WIRE_SIZE
+};
+
 /// Socket-free snapclient protocol core. It never reads or writes bytes; the
 /// caller drives it by feeding `Event`s (satisfying the requested `next_action`)
 /// and draining `poll_transmit`, injecting a monotonic `now_us` for every step.
 pub struct ClientMachine {
     framing: Framing,
-    latency_buf: CircularBuffer<LATENCY_SAMPLES, TimeVal>,
-    sorted_latency_buf: Vec<TimeVal>,
+    clock: ClockModel,
     pkt_id: u16,
     server_buffer_ms: TimeVal,
     local_latency: TimeVal,
-    /// Client timestamp stamped into the last Time request; read by the offset math.
-    last_sent_time: TimeVal,
+    /// `(id, client send time)` of Time requests awaiting a reply, oldest first.
+    outstanding: CircularBuffer<OUTSTANDING, (u16, i64)>,
+    /// Recent round-trip times, kept or discarded.
+    rtts: CircularBuffer<RTT_WINDOW, i64>,
     /// When the last Time request was emitted; gates the request cadence.
     last_time_sent_us: i64,
-    /// Median server-to-client clock offset.
+    /// Server-to-client clock offset predicted at the last Time exchange.
     clock_offset: TimeVal,
+    /// Local volume/mute waiting to be reported to the server.
+    pending_info: Option<ClientInfo>,
 }
 
 impl Default for ClientMachine {
@@ -49,47 +114,75 @@ impl ClientMachine {
         let tv_zero = TimeVal { sec: 0, usec: 0 };
         ClientMachine {
             framing: Framing::new(),
-            latency_buf: CircularBuffer::new(),
-            sorted_latency_buf: vec![tv_zero; LATENCY_SAMPLES],
+            clock: ClockModel::new(),
             pkt_id: 0,
             server_buffer_ms: TimeVal {
                 sec: 0,
                 usec: 999_999,
             },
             local_latency: tv_zero,
-            last_sent_time: tv_zero,
+            outstanding: CircularBuffer::new(),
+            rtts: CircularBuffer::new(),
             last_time_sent_us: 0,
             clock_offset: TimeVal {
                 sec: 0,
                 usec: 1_000,
             },
+            pending_info: None,
         }
     }
 
     pub fn synchronized(&self) -> bool {
-        self.latency_buf.len() == self.sorted_latency_buf.len()
+        self.clock.len() >= LATENCY_SAMPLES
     }
 
-    /// Median server-to-client clock offset out of the last measurements.
+    /// Server-to-client clock offset predicted at the last Time exchange.
     pub fn clock_offset(&self) -> TimeVal {
         self.clock_offset
     }
 
+    /// How much faster the server clock runs than ours, in parts per million.
+    pub fn drift_ppm(&self) -> f64 {
+        self.clock.drift_ppm()
+    }
+
     pub fn next_action(&self) -> Action {
         self.framing.next_action()
     }
 
-    /// Emit a timer-driven Time request into `out` when one is due, returning its
-    /// length. Dense sampling (>=1ms apart) until the offset buffer fills, once a
-    /// second afterwards. `out` must hold at least [`Time::WIRE_SIZE`] bytes.
+    /// When [`ClientMachine::poll_transmit`] next has a Time request to send,
+    /// for drivers that sleep on a timer rather than polling.
+    pub fn next_transmit_us(&self) -> i64 {
+        let interval = if self.synchronized() {
+            1_000_000
+        } else {
+            1_000
+        };
+        self.last_time_sent_us.saturating_add(interval)
+    }
+
+    /// Queue a ClientInfo reporting a local volume/mute change; it goes out on
+    /// the next [`ClientMachine::poll_transmit`]. A newer report replaces one
+    /// that was not sent yet.
+    pub fn send_client_info(&mut self, volume: u8, muted: bool) {
+        self.pending_info = Some(ClientInfo { volume, muted });
+    }
+
+    /// Emit the next outgoing message into `out`, returning its length: a queued
+    /// ClientInfo first, then a timer-driven Time request when one is due. Dense
+    /// sampling (>=1ms apart) until the offset buffer fills, once a second
+    /// afterwards. `out` must hold at least [`MAX_TRANSMIT`] bytes.
     pub fn poll_transmit(&mut self, now_us: i64, out: &mut [u8]) -> Option<usize> {
-        let elapsed = now_us - self.last_time_sent_us;
-        let due = (!self.synchronized() && elapsed >= 1_000) || elapsed >= 1_000_000;
-        if !due {
+        if let Some(info) = self.pending_info.take() {
+            let n = info.write(out, self.pkt_id, TimeVal::from_micros(now_us));
+            self.pkt_id = self.pkt_id.wrapping_add(1);
+            return Some(n);
+        }
+        if now_us < self.next_transmit_us() {
             return None;
         }
         let tv = TimeVal::from_micros(now_us);
-        self.last_sent_time = tv;
+        self.outstanding.push_back((self.pkt_id, now_us));
         self.last_time_sent_us = now_us;
         let n = Time::write(out, self.pkt_id, 0, tv, tv, tv);
         self.pkt_id = self.pkt_id.wrapping_add(1);
@@ -107,12 +200,31 @@ impl ClientMachine {
                 Ok(Message::Nothing)
             }
             Event::PacketReceived(bytes) => {
-                let base = self.framing.take_base();
+                let base = self.framing.take_base()?;
                 self.process_packet(base, bytes, now_us)
             }
         }
     }
 
+    /// Send time of the Time request `id`, forgetting it and any request sent
+    /// before it, which are presumed lost; `None` if `id` is not outstanding.
+    fn take_request(&mut self, id: u16) -> Option<i64> {
+        let pos = self.outstanding.iter().position(|(i, _)| *i == id)?;
+        let (_, sent_us) = self.outstanding[pos];
+        for _ in 0..=pos {
+            self.outstanding.pop_front();
+        }
+        Some(sent_us)
+    }
+
+    /// NTP-style filter: records `rtt` and tells whether it is close enough to
+    /// the best recent round trip for its offset to be trusted.
+    fn rtt_acceptable(&mut self, rtt: i64) -> bool {
+        self.rtts.push_back(rtt);
+        let min = self.rtts.iter().copied().min().unwrap_or(rtt);
+        rtt.saturating_sub(min) <= min.max(MIN_RTT_SLACK_US)
+    }
+
     fn process_packet<'a>(
         &mut self,
         base: Base,
@@ -122,28 +234,45 @@ impl ClientMachine {
         let recv_ts = TimeVal::from_micros(now_us);
         Ok(match base.decode(payload)? {
             ServerMessage::Time(_) => {
+                let Some(sent_us) = self.take_request(base.refers_to) else {
+                    log::debug!("time reply to unknown request {}", base.refers_to);
+                    return Ok(Message::Nothing);
+                };
+                // time the server held the request is not network delay
+                let held_us = (base.sent_tv - base.received_tv).to_micros();
+                let rtt = now_us.saturating_sub(sent_us).saturating_sub(held_us);
+                if !self.rtt_acceptable(rtt) {
+                    // most likely queued in one direction: its offset is off by
+                    // half the excess, so leave it out entirely
+                    return Ok(Message::Nothing);
+                }
+
                 // c2s = clock_offset + uplink_delay; s2c = -clock_offset + downlink_delay
                 // their difference cancels the (symmetric) network delay, leaving the
                 // server-to-client clock offset; summing would cancel the offset instead
-                let c2s = base.received_tv - self.last_sent_time;
+                let c2s = base.received_tv - TimeVal::from_micros(sent_us);
                 let s2c = recv_ts - base.sent_tv;
                 let diff = c2s - s2c;
                 // TimeVal::div truncates sec and usec separately, which loses up to
                 // 500ms when sec is odd; divide in microseconds instead
-                let offset = TimeVal::from_micros(diff.to_micros() / 2).normalize();
-
-                self.latency_buf.push_back(offset);
-                for (i, tv) in self.latency_buf.iter().enumerate() {
-                    self.sorted_latency_buf[i] = *tv;
+                let offset = diff.to_micros() / 2;
+                // the exchange measured the offset halfway between send and receive
+                let at_us = sent_us.saturating_add(now_us.saturating_sub(sent_us) / 2);
+                self.clock.add_sample(at_us, offset);
+                if let Some(o) = self.clock.offset_at(at_us) {
+                    self.clock_offset = TimeVal::from_micros(o).normalize();
                 }
-                self.sorted_latency_buf.sort();
-                let slb_len = self.sorted_latency_buf.len();
-                let nz_samples = &self.sorted_latency_buf[slb_len - self.latency_buf.len()..];
-                self.clock_offset = nz_samples[nz_samples.len() / 2];
                 Message::Nothing
             }
             ServerMessage::WireChunk(wc) => {
-                let t_c = wc.timestamp - self.clock_offset;
+                // predict the offset at the chunk's own time rather than at the last
+                // exchange, so drift since then does not leak into the schedule
+                let chunk_us = (wc.timestamp - self.clock_offset).to_micros();
+                let offset = match self.clock.offset_at(chunk_us) {
+                    Some(o) => TimeVal::from_micros(o).normalize(),
+                    None => self.clock_offset,
+                };
+                let t_c = wc.timestamp - offset;
                 let audible_at = t_c + self.server_buffer_ms - self.local_latency;
 
                 let cmp = audible_at - recv_ts;
@@ -162,13 +291,23 @@ impl ClientMachine {
                 Message::ServerSettings(s)
             }
             ServerMessage::CodecHeader(ch) => Message::CodecHeader(ch),
+            ServerMessage::StreamTags(t) => Message::StreamTags(t),
         })
     }
 }
 
+/// Who the client says it is in its Hello; the server keeps per-client
+/// settings (volume, latency, group) under the ID.
 pub struct Client {
-    mac: String,
+    /// `None` looks up the MAC of the interface that reaches the server, on
+    /// every connection.
+    mac: Option<String>,
     hostname: String,
+    /// Defaults to the MAC.
+    id: Option<String>,
+    /// Tells apart several clients on one host.
+    instance: u8,
+    client_name: String,
 }
 
 /// Blocking imperative shell around [`ClientMachine`]: owns the TcpStream and the
@@ -177,13 +316,12 @@ pub struct ConnectedClient {
     conn: TcpStream,
     time_base: Instant,
     machine: ClientMachine,
-    hdr_buf: Vec<u8>,
-    pkt_buf: Vec<u8>,
-    tx_buf: [u8; Time::WIRE_SIZE],
+    rx: RxBuf,
+    tx_buf: [u8; MAX_TRANSMIT],
 }
 
 impl ConnectedClient {
-    fn new(conn: TcpStream) -> anyhow::Result<ConnectedClient> {
+    fn new(conn: TcpStream, time_base: Instant) -> anyhow::Result<ConnectedClient> {
         match conn.set_nodelay(true) {
             Ok(()) => (),
             Err(e) => log::error!("Failed to set nodelay on connection: {:?}", e),
@@ -195,11 +333,10 @@ impl ConnectedClient {
         conn.set_write_timeout(Some(Duration::from_secs(3)))?;
         Ok(ConnectedClient {
             conn,
-            time_base: Instant::now(),
+            time_base,
             machine: ClientMachine::new(),
-            hdr_buf: vec![0; Base::BASE_SIZE],
-            pkt_buf: vec![0; 9000],
-            tx_buf: [0; Time::WIRE_SIZE],
+            rx: RxBuf::new(),
+            tx_buf: [0; MAX_TRANSMIT],
         })
     }
 
@@ -211,15 +348,25 @@ impl ConnectedClient {
         self.machine.synchronized()
     }
 
-    /// Median server-to-client clock offset out of the last measurements.
+    /// Server-to-client clock offset predicted at the last Time exchange.
     pub fn clock_offset(&self) -> TimeVal {
         self.machine.clock_offset()
     }
 
+    /// How much faster the server clock runs than ours, in parts per million.
+    pub fn drift_ppm(&self) -> f64 {
+        self.machine.drift_ppm()
+    }
+
     pub fn time_base(&self) -> Instant {
         self.time_base
     }
 
+    /// Report a local volume/mute change to the server on the next `tick`.
+    pub fn send_client_info(&mut self, volume: u8, muted: bool) {
+        self.machine.send_client_info(volume, muted);
+    }
+
     pub fn tick(&mut self) -> anyhow::Result<Message<'_>> {
         let tx_now = self.now_us();
         while let Some(n) = self.machine.poll_transmit(tx_now, &mut self.tx_buf) {
@@ -229,63 +376,108 @@ impl ConnectedClient {
         }
 
         loop {
-            match self.machine.next_action() {
-                Action::ReadHeader => match self.conn.read_exact(&mut self.hdr_buf) {
-                    Ok(()) => {
-                        let ev = Event::HeaderReceived(&self.hdr_buf);
-                        // returns Nothing and transitions to ReadPacket; loop to read it
-                        self.machine.handle_event(ev, tx_now)?;
-                    }
-                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        return Ok(Message::Nothing);
-                    }
-                    Err(e) => return Err(e.into()),
-                },
-                Action::ReadPacket(size) => {
-                    let size = size as usize;
-                    if size > self.pkt_buf.len() {
-                        log::warn!("Resizing pkt buf to {}", size);
-                        // pcm data is up to 4880b; flac is up to 9k~
-                        self.pkt_buf.resize(size, 0);
-                    }
-                    match self.conn.read_exact(&mut self.pkt_buf[0..size]) {
-                        Ok(()) => {
-                            let rx_now = self.now_us();
-                            let ev = Event::PacketReceived(&self.pkt_buf[0..size]);
-                            return self.machine.handle_event(ev, rx_now);
-                        }
-                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                            return Ok(Message::Nothing);
-                        }
-                        Err(e) => return Err(e.into()),
-                    }
-                }
+            let action = self.machine.next_action();
+            let size = action.size();
+            // a timeout part-way through a frame keeps what was read for the next
+            // tick, so a slow link cannot desynchronise the framing
+            if !self.rx.read_from(&mut self.conn, size)? {
+                return Ok(Message::Nothing);
+            }
+            let rx_now = self.now_us();
+            if let Action::ReadPacket(_) = action {
+                let ev = Event::PacketReceived(self.rx.take(size));
+                return self.machine.handle_event(ev, rx_now);
             }
+            let ev = Event::HeaderReceived(self.rx.take(size));
+            // returns Nothing and transitions to ReadPacket; loop to read it
+            self.machine.handle_event(ev, rx_now)?;
         }
     }
 }
 
 impl Client {
     pub fn connect<A: ToSocketAddrs>(&self, dst: A) -> anyhow::Result<ConnectedClient> {
+        self.connect_at(dst, Instant::now())
+    }
+
+    /// Like [`Client::connect`], but measuring time from `time_base` instead of
+    /// now, so audible times from an earlier connection stay comparable.
+    pub fn connect_at<A: ToSocketAddrs>(
+        &self,
+        dst: A,
+        time_base: Instant,
+    ) -> anyhow::Result<ConnectedClient> {
         let conn = TcpStream::connect(dst)?;
-        let mut cc = ConnectedClient::new(conn)?;
+        let hello = self.hello(conn.peer_addr().ok());
+        let mut cc = ConnectedClient::new(conn, time_base)?;
+        cc.conn.write_all(&hello)?;
+        Ok(cc)
+    }
 
-        let hello = ClientHello {
+    /// The Hello every connection to `server` opens with.
+    pub(crate) fn hello(&self, server: Option<SocketAddr>) -> Vec<u8> {
+        let mac = match &self.mac {
+            Some(mac) => mac.clone(),
+            None => server
+                .and_then(|s| identity::mac_for(s.ip()))
+                .unwrap_or_else(|| {
+                    log::warn!("no MAC found for the route to the server");
+                    "00:00:00:00:00:00".to_string()
+                }),
+        };
+        ClientHello {
             Arch: std::env::consts::ARCH,
-            ClientName: "CoolClient",
+            ClientName: &self.client_name,
             HostName: &self.hostname,
-            ID: &self.mac,
-            Instance: 1,
-            MAC: &self.mac,
+            ID: self.id.as_deref().unwrap_or(&mac),
+            Instance: self.instance,
+            MAC: &mac,
             SnapStreamProtocolVersion: 2,
             Version: "0.17.1",
             OS: std::env::consts::OS,
-        };
-        cc.conn.write_all(&hello.as_buf())?;
-        Ok(cc)
+        }
+        .as_buf()
     }
+
     pub fn new(mac: String, hostname: String) -> Client {
-        Client { mac, hostname }
+        Client::from_system().with_mac(mac).with_hostname(hostname)
+    }
+
+    /// Identity from the system: its hostname, and the MAC of whichever
+    /// interface reaches the server.
+    pub fn from_system() -> Client {
+        Client {
+            mac: None,
+            hostname: identity::hostname().unwrap_or_else(|| "snapclient".to_string()),
+            id: None,
+            instance: 1,
+            client_name: "CoolClient".to_string(),
+        }
+    }
+
+    pub fn with_mac(mut self, mac: String) -> Client {
+        self.mac = Some(mac);
+        self
+    }
+
+    pub fn with_hostname(mut self, hostname: String) -> Client {
+        self.hostname = hostname;
+        self
+    }
+
+    pub fn with_id(mut self, id: String) -> Client {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn with_instance(mut self, instance: u8) -> Client {
+        self.instance = instance;
+        self
+    }
+
+    pub fn with_client_name(mut self, name: String) -> Client {
+        self.client_name = name;
+        self
     }
 }
 
@@ -324,6 +516,36 @@ mod tests {
         assert_eq!(n, Time::WIRE_SIZE);
     }
 
+    #[test]
+    fn client_info_goes_out_before_time_requests() {
+        let mut m = ClientMachine::new();
+        let mut buf = [0u8; MAX_TRANSMIT];
+        m.send_client_info(30, false);
+        // superseded before it was sent: only the latest report goes out
+        m.send_client_info(40, true);
+
+        let n = m.poll_transmit(1_000, &mut buf).unwrap();
+        let base = Base::try_from(&buf[0..Base::BASE_SIZE]).unwrap();
+        assert_eq!(base.id, 0);
+        match base.decode_c(&buf[Base::BASE_SIZE..n]).unwrap() {
+            ClientMessage::ClientInfo(ci) => assert_eq!(
+                ci,
+                ClientInfo {
+                    volume: 40,
+                    muted: true
+                }
+            ),
+            other => panic!("expected ClientInfo, got {other:?}"),
+        }
+
+        // the due Time request follows on the next poll, with the next id
+        let n = m.poll_transmit(1_000, &mut buf).unwrap();
+        assert_eq!(n, Time::WIRE_SIZE);
+        let base = Base::try_from(&buf[0..Base::BASE_SIZE]).unwrap();
+        assert_eq!(base.id, 1);
+        assert!(m.poll_transmit(1_000, &mut buf).is_none());
+    }
+
     // Drives a Time round-trip with a known clock offset and symmetric one-way
     // delay: the client sends at client-time `s_us`, the server stamps both its
     // received/sent at `s_us + offset + delay`, and the reply lands at
@@ -333,13 +555,58 @@ mod tests {
         m.poll_transmit(s_us, &mut buf)
             .expect("time request should be due");
         let server_t = TimeVal::from_micros(s_us + offset_us + delay_us);
-        let reply = Time::as_buf(0, 0, server_t, server_t, TimeVal { sec: 0, usec: 0 });
+        answer(m, &buf, server_t, s_us + 2 * delay_us);
+    }
+
+    /// Reply to the Time request in `req`, the server reading `server_t` off its
+    /// clock and the reply landing at client time `recv_us`.
+    fn answer(m: &mut ClientMachine, req: &[u8], server_t: TimeVal, recv_us: i64) {
+        let id = Base::try_from(&req[0..Base::BASE_SIZE]).unwrap().id;
+        let reply = Time::as_buf(0, id, server_t, server_t, TimeVal { sec: 0, usec: 0 });
         let (hdr, payload) = reply.split_at(Base::BASE_SIZE);
         m.handle_event(Event::HeaderReceived(hdr), 0).unwrap();
-        m.handle_event(Event::PacketReceived(payload), s_us + 2 * delay_us)
+        m.handle_event(Event::PacketReceived(payload), recv_us)
             .unwrap();
     }
 
+    #[test]
+    fn replies_are_paired_with_their_request() {
+        let mut m = ClientMachine::new();
+        let (mut a, mut b) = ([0u8; 64], [0u8; 64]);
+        m.poll_transmit(1_000, &mut a).unwrap();
+        m.poll_transmit(2_000, &mut b).unwrap();
+
+        // a's reply arrives after b went out: 50ms offset, 10ms each way from a's send
+        let server_t = TimeVal::from_micros(1_000 + 50_000 + 10_000);
+        answer(&mut m, &a, server_t, 21_000);
+        assert_eq!(m.clock_offset(), TimeVal::from_micros(50_000));
+
+        // a reply to a request never sent (or long given up on) is ignored
+        let mut bogus = b;
+        bogus[2] = 77;
+        answer(&mut m, &bogus, TimeVal::from_micros(0), 22_000);
+        assert_eq!(m.clock_offset(), TimeVal::from_micros(50_000));
+    }
+
+    #[test]
+    fn slow_round_trips_are_discarded() {
+        let mut m = ClientMachine::new();
+        feed_time_reply(&mut m, 1_000, 50_000, 1_000);
+        assert_eq!(m.clock_offset(), TimeVal::from_micros(50_000));
+
+        // the request sat 40ms in a queue on the way out, which would read as
+        // a 70ms offset
+        let mut req = [0u8; 64];
+        m.poll_transmit(10_000, &mut req).unwrap();
+        let server_t = TimeVal::from_micros(10_000 + 50_000 + 41_000);
+        answer(&mut m, &req, server_t, 52_000);
+        assert_eq!(m.clock_offset(), TimeVal::from_micros(50_000));
+
+        // an ordinary round trip right after is used again
+        feed_time_reply(&mut m, 20_000, 60_000, 1_000);
+        assert_ne!(m.clock_offset(), TimeVal::from_micros(50_000));
+    }
+
     #[test]
     fn offset_converges_to_known_value() {
         let mut m = ClientMachine::new();
@@ -351,6 +618,20 @@ mod tests {
         assert_eq!(m.clock_offset(), TimeVal::from_micros(offset));
     }
 
+    #[test]
+    fn drifting_server_clock_is_tracked() {
+        let mut m = ClientMachine::new();
+        // the server clock gains 30ppm on ours
+        let offset = |t: i64| 20_000 + t * 30 / 1_000_000;
+        for k in 1..=120 {
+            let s_us = k * 1_000_000;
+            feed_time_reply(&mut m, s_us, offset(s_us), 5_000);
+        }
+        assert!((m.drift_ppm() - 30.0).abs() < 1.0, "{}", m.drift_ppm());
+        let err = m.clock_offset().to_micros() - offset(120_000_000);
+        assert!(err.abs() < 10, "offset off by {err}us");
+    }
+
     fn feed_wire_chunk(m: &mut ClientMachine, ts: TimeVal, now_us: i64) -> Message<'static> {
         // as_buf owns its bytes; leak them so the returned Message can borrow 'static
         let data: &'static [u8] = Box::leak(vec![0u8; 8].into_boxed_slice());
@@ -0,0 +1,204 @@
+use circular_buffer::CircularBuffer;
+
+/// Samples the fit looks back over: at one Time exchange a second, two
+/// minutes, which is long enough for tens of ppm of drift to rise well above
+/// network jitter.
+const WINDOW: usize = 120;
+
+/// Below this span the skew cannot be told apart from network jitter, so the
+/// model stays offset-only (the median, as before) until enough history exists.
+const MIN_SKEW_SPAN_US: i64 = 10_000_000;
+
+/// Real oscillators stay far inside this; a larger fitted skew is noise.
+const MAX_DRIFT_PPM: f64 = 500.0;
+
+/// Residuals are never rejected below this, so a quiet LAN with near-zero
+/// jitter does not throw away good samples.
+const MIN_OUTLIER_US: f64 = 200.0;
+
+/// Estimates the server clock as `offset + skew * t` over client time `t`,
+/// from the offsets measured by Time exchanges. A plain median lags a drifting
+/// clock by half the window; predicting from the fitted line does not.
+pub struct ClockModel {
+    /// `(client_us, offset_us)` of each measurement, oldest first.
+    samples: CircularBuffer<WINDOW, (i64, i64)>,
+    scratch: Vec<f64>,
+    /// Client time the fit is anchored at (the newest sample).
+    t_ref: i64,
+    offset_at_ref: f64,
+    /// Server microseconds gained per client microsecond.
+    skew: f64,
+}
+
+impl Default for ClockModel {
+    fn default() -> ClockModel {
+        ClockModel::new()
+    }
+}
+
+impl ClockModel {
+    pub fn new() -> ClockModel {
+        ClockModel {
+            samples: CircularBuffer::new(),
+            scratch: Vec::with_capacity(WINDOW),
+            t_ref: 0,
+            offset_at_ref: 0.0,
+            skew: 0.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Record that at client time `client_us` the server clock was `offset_us`
+    /// ahead, and refit.
+    pub fn add_sample(&mut self, client_us: i64, offset_us: i64) {
+        self.samples.push_back((client_us, offset_us));
+        self.fit();
+    }
+
+    /// Server-to-client offset predicted at client time `client_us`, or `None`
+    /// before the first sample.
+    pub fn offset_at(&self, client_us: i64) -> Option<i64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let dt = client_us.wrapping_sub(self.t_ref) as f64;
+        Some((self.offset_at_ref + self.skew * dt).round() as i64)
+    }
+
+    /// How much faster the server clock runs than ours, in parts per million.
+    pub fn drift_ppm(&self) -> f64 {
+        self.skew * 1e6
+    }
+
+    fn fit(&mut self) {
+        let Some(&(newest, _)) = self.samples.back() else {
+            return;
+        };
+        let (oldest, _) = self.samples[0];
+        self.t_ref = newest;
+        if newest.wrapping_sub(oldest) < MIN_SKEW_SPAN_US {
+            self.skew = 0.0;
+            self.offset_at_ref = self.median(|_, o| o as f64);
+            return;
+        }
+
+        let (skew, offset) = self.least_squares((0.0, 0.0), f64::INFINITY);
+        // drop samples that took a congested path, then fit the rest
+        let mad = self.median(|t, o| (o as f64 - offset - skew * t).abs());
+        let (skew, offset) = self.least_squares((skew, offset), (3.0 * mad).max(MIN_OUTLIER_US));
+
+        self.skew = skew.clamp(-MAX_DRIFT_PPM / 1e6, MAX_DRIFT_PPM / 1e6);
+        // keep the line through the inliers when the skew was clamped
+        self.offset_at_ref = offset + (skew - self.skew) * self.mean_t();
+    }
+
+    /// Median of `f(t, offset)` over the window, `t` relative to `t_ref`.
+    fn median(&mut self, f: impl Fn(f64, i64) -> f64) -> f64 {
+        self.scratch.clear();
+        for &(t, o) in self.samples.iter() {
+            self.scratch.push(f(t.wrapping_sub(self.t_ref) as f64, o));
+        }
+        self.scratch.sort_by(f64::total_cmp);
+        self.scratch[self.scratch.len() / 2]
+    }
+
+    fn mean_t(&self) -> f64 {
+        let sum: f64 = self
+            .samples
+            .iter()
+            .map(|&(t, _)| t.wrapping_sub(self.t_ref) as f64)
+            .sum();
+        sum / self.samples.len() as f64
+    }
+
+    /// `(skew, offset at t_ref)` fitted over the samples whose residual against
+    /// `line` is within `max_residual`.
+    fn least_squares(&self, line: (f64, f64), max_residual: f64) -> (f64, f64) {
+        let (mut n, mut st, mut so, mut stt, mut sto) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for &(t, o) in self.samples.iter() {
+            let t = t.wrapping_sub(self.t_ref) as f64;
+            let o = o as f64;
+            if (o - line.1 - line.0 * t).abs() > max_residual {
+                continue;
+            }
+            n += 1.0;
+            st += t;
+            so += o;
+            stt += t * t;
+            sto += t * o;
+        }
+        let var = n * stt - st * st;
+        if n < 2.0 || var == 0.0 {
+            return (0.0, so / n.max(1.0));
+        }
+        let skew = (n * sto - st * so) / var;
+        (skew, (so - skew * st) / n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic +-`amp` jitter, so the tests do not depend on a RNG crate.
+    fn jitter(i: i64, amp: i64) -> i64 {
+        (i * 7919 % 201 - 100) * amp / 100
+    }
+
+    #[test]
+    fn constant_offset_is_exact() {
+        let mut c = ClockModel::new();
+        assert_eq!(c.offset_at(0), None);
+        for i in 0..60 {
+            c.add_sample(i * 1_000_000, 50_000);
+        }
+        assert_eq!(c.offset_at(60_000_000), Some(50_000));
+        assert_eq!(c.drift_ppm(), 0.0);
+    }
+
+    #[test]
+    fn tracks_drift_through_jitter() {
+        let mut c = ClockModel::new();
+        // server runs 40ppm fast, with +-300us of network jitter
+        let offset = |t: i64| 1_000_000 + t * 40 / 1_000_000;
+        for i in 0..WINDOW as i64 {
+            let t = i * 1_000_000;
+            c.add_sample(t, offset(t) + jitter(i, 300));
+        }
+        assert!((c.drift_ppm() - 40.0).abs() < 2.0, "{}", c.drift_ppm());
+        // a median would lag the true offset by ~2.4ms here
+        let t = 125_000_000;
+        let err = c.offset_at(t).unwrap() - offset(t);
+        assert!(err.abs() < 200, "predicted offset off by {err}us");
+    }
+
+    #[test]
+    fn outliers_do_not_bend_the_fit() {
+        let mut c = ClockModel::new();
+        for i in 0..60i64 {
+            // every fifth exchange got stuck behind a 40ms burst
+            let delay = if i % 5 == 0 { 40_000 } else { 0 };
+            c.add_sample(i * 1_000_000, 10_000 + delay);
+        }
+        assert!(c.drift_ppm().abs() < 1.0, "{}", c.drift_ppm());
+        assert_eq!(c.offset_at(60_000_000), Some(10_000));
+    }
+
+    #[test]
+    fn short_history_is_offset_only() {
+        let mut c = ClockModel::new();
+        // the initial burst of exchanges a millisecond apart
+        for i in 0..20 {
+            c.add_sample(i * 1_000, 5_000 + jitter(i, 300));
+        }
+        assert_eq!(c.drift_ppm(), 0.0);
+        assert!((c.offset_at(0).unwrap() - 5_000).abs() <= 300);
+    }
+}
@@ -1,6 +1,14 @@
+#[cfg(feature = "vorbis")]
+use crate::ogg::OggDemuxer;
+#[cfg(feature = "flac")]
+use crate::proto::FlacMetadata;
 #[cfg(feature = "opus")]
 use crate::proto::OpusMetadata;
-#[cfg(feature = "opus")]
+use crate::proto::PcmMetadata;
+#[cfg(feature = "vorbis")]
+use crate::proto::VorbisMetadata;
+use crate::sample::SampleFormat;
+#[cfg(any(feature = "opus", feature = "vorbis"))]
 use anyhow::Context;
 use anyhow::Result;
 use enum_dispatch::enum_dispatch;
@@ -8,110 +16,424 @@ use enum_dispatch::enum_dispatch;
 #[cfg(feature = "flac")]
 use claxon::frame::FrameReader;
 
+#[cfg(feature = "vorbis")]
+use lewton::audio::{read_audio_packet_generic, PreviousWindowRight};
+#[cfg(feature = "vorbis")]
+use lewton::header::{read_header_ident, read_header_setup, IdentHeader, SetupHeader};
+#[cfg(feature = "vorbis")]
+use lewton::samples::InterleavedSamples;
+
+#[cfg(feature = "opus")]
+use core::mem::MaybeUninit;
 #[cfg(feature = "opus")]
 use opus_embedded;
 
+/// Opus, with its state in a heap slot it owns, so dropping the decoder frees
+/// it and a reset reinitializes it in place.
 #[cfg(feature = "opus")]
-pub struct OpusDecoder<'a>(&'a mut opus_embedded::Decoder);
+pub struct OpusDecoder {
+    state: Box<MaybeUninit<opus_embedded::Decoder>>,
+    /// Set once `init_in` succeeded on `state`.
+    ready: bool,
+    channels: usize,
+    rate: usize,
+    /// opus decodes to i16; widened into the caller's buffer
+    pcm: Vec<i16>,
+}
 
 #[enum_dispatch(Decode)]
-pub enum Decoder<'a> {
+pub enum Decoder {
     #[cfg(feature = "opus")]
-    Opus(OpusDecoder<'a>),
-    PCM(NoOpDecoder),
+    Opus(OpusDecoder),
+    PCM(PcmDecoder),
     #[cfg(feature = "flac")]
     Flac(FlacDecoder),
+    #[cfg(feature = "vorbis")]
+    Vorbis(VorbisDecoder),
 }
 
-impl<'a> Decoder<'a> {
-    pub fn new_pcm() -> Decoder<'a> {
-        Decoder::PCM(NoOpDecoder {})
+impl Decoder {
+    pub fn new_pcm(config: &PcmMetadata) -> Decoder {
+        Decoder::PCM(PcmDecoder::new(
+            config.format,
+            config.channel_count as usize,
+        ))
     }
 
     #[cfg(feature = "flac")]
-    pub fn new_flac() -> Decoder<'a> {
-        Decoder::Flac(FlacDecoder::new())
+    pub fn new_flac(config: &FlacMetadata) -> anyhow::Result<Decoder> {
+        anyhow::ensure!(
+            (4..=32).contains(&config.bit_depth),
+            "unsupported flac sample size: {} bits",
+            config.bit_depth
+        );
+        Ok(Decoder::Flac(FlacDecoder::new(config.bit_depth)))
+    }
+
+    #[cfg(feature = "vorbis")]
+    pub fn new_vorbis(config: &VorbisMetadata) -> anyhow::Result<Decoder> {
+        Ok(Decoder::Vorbis(VorbisDecoder::new(config)?))
     }
 
     #[cfg(feature = "opus")]
-    pub fn new_opus(
-        config: &OpusMetadata,
-        slot: &'a mut core::mem::MaybeUninit<opus_embedded::Decoder>,
-    ) -> anyhow::Result<Decoder<'a>> {
-        let c = match config.channel_count {
+    pub fn new_opus(config: &OpusMetadata) -> anyhow::Result<Decoder> {
+        Ok(Decoder::Opus(OpusDecoder::new(config)?))
+    }
+}
+
+#[enum_dispatch]
+pub trait Decode {
+    /// Decode one chunk into full-scale samples (see
+    /// [`crate::sample::SampleEncoder`]). Returns total number of samples
+    fn decode_sample(&mut self, buf: &[u8], out: &mut [i32]) -> Result<usize, anyhow::Error>;
+
+    /// Make up at most `frames` frames for audio that never arrived; returns
+    /// the number of samples written, 0 when the codec cannot conceal losses
+    /// and the caller should play silence.
+    fn conceal(&mut self, _frames: usize, _out: &mut [i32]) -> Result<usize, anyhow::Error> {
+        Ok(0)
+    }
+
+    /// Forget everything carried between chunks, for audio that does not
+    /// follow on from the last chunk (e.g. after a reconnect).
+    fn reset(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "opus")]
+impl OpusDecoder {
+    pub fn new(config: &OpusMetadata) -> anyhow::Result<OpusDecoder> {
+        let channels = config.channel_count as usize;
+        let rate = config.sample_rate as usize;
+        let mut dec = OpusDecoder {
+            state: Box::new(MaybeUninit::uninit()),
+            ready: false,
+            channels,
+            rate,
+            // 120ms, the longest opus frame
+            pcm: vec![0; rate / 1000 * 120 * channels],
+        };
+        dec.init()?;
+        Ok(dec)
+    }
+
+    /// (Re)initialize the opus state in place.
+    fn init(&mut self) -> anyhow::Result<()> {
+        let c = match self.channels {
             1 => opus_embedded::Channels::Mono,
             2 => opus_embedded::Channels::Stereo,
-            _ => panic!("unsupported channel configuration"),
+            n => anyhow::bail!("opus supports mono and stereo, not {n} channels"),
         };
-        let s = match config.sample_rate {
+        let s = match self.rate {
+            8_000 => opus_embedded::SamplingRate::F8k,
+            12_000 => opus_embedded::SamplingRate::F12k,
+            16_000 => opus_embedded::SamplingRate::F16k,
+            24_000 => opus_embedded::SamplingRate::F24k,
             48_000 => opus_embedded::SamplingRate::F48k,
-            _ => panic!("only supports 48_000 sampling rate for opus"),
+            r => anyhow::bail!("opus supports 8, 12, 16, 24 and 48kHz, not {r}Hz"),
         };
-        let dec = opus_embedded::Decoder::init_in(slot, s, c)
+        self.ready = false;
+        opus_embedded::Decoder::init_in(&mut self.state, s, c)
             .map_err(|e| anyhow::anyhow!("making opus decoder: {e}"))?;
-        Ok(Decoder::Opus(OpusDecoder(dec)))
+        self.ready = true;
+        Ok(())
+    }
+
+    /// Decode `packet` into the first `len` samples of `pcm`, an empty one
+    /// standing for a lost packet; returns the samples written.
+    fn decode(&mut self, packet: &[u8], len: usize) -> anyhow::Result<usize> {
+        anyhow::ensure!(self.ready, "opus decoder failed to reinitialize");
+        // SAFETY: `ready` is only set once `init_in` has written the state
+        let dec = unsafe { self.state.assume_init_mut() };
+        Ok(dec.decode(packet, &mut self.pcm[..len])?.len())
     }
 }
 
-#[enum_dispatch]
-pub trait Decode<'a> {
-    /// Returns total number of samples
-    fn decode_sample(&mut self, buf: &[u8], out: &mut [i16]) -> Result<usize, anyhow::Error>;
+#[cfg(feature = "opus")]
+impl Decode for OpusDecoder {
+    fn decode_sample(&mut self, buf: &[u8], out: &mut [i32]) -> Result<usize, anyhow::Error> {
+        let len = self.pcm.len().min(out.len());
+        let n = self.decode(buf, len).context("decode")?;
+        widen(&self.pcm[..n], out);
+        Ok(n)
+    }
+
+    fn conceal(&mut self, frames: usize, out: &mut [i32]) -> Result<usize, anyhow::Error> {
+        // PLC works in multiples of 2.5ms, up to 120ms per call
+        let step = self.rate / 400;
+        let frames = frames.min(out.len() / self.channels).min(step * 48);
+        let frames = frames - frames % step;
+        if frames == 0 {
+            return Ok(0);
+        }
+        // an empty packet tells opus it was lost: it extrapolates from the
+        // previous frames, fading to silence over longer gaps
+        let n = self
+            .decode(&[], frames * self.channels)
+            .context("conceal")?;
+        widen(&self.pcm[..n], out);
+        Ok(n)
+    }
+
+    fn reset(&mut self) -> Result<(), anyhow::Error> {
+        self.init()
+    }
 }
 
 #[cfg(feature = "opus")]
-impl<'a> Decode<'a> for OpusDecoder<'a> {
-    fn decode_sample(&mut self, buf: &[u8], out: &mut [i16]) -> Result<usize, anyhow::Error> {
-        // TODO: fec?
-        Ok(self.0.decode(buf, out).context("decode")?.len())
+fn widen(pcm: &[i16], out: &mut [i32]) {
+    for (o, &s) in out.iter_mut().zip(pcm) {
+        *o = (s as i32) << 16;
     }
 }
 
-pub struct NoOpDecoder;
+/// Raw little-endian PCM, at any [`SampleFormat`]. A frame split across
+/// chunks is held back and completed from the next one, so channels never
+/// shift.
+pub struct PcmDecoder {
+    format: SampleFormat,
+    frame_bytes: usize,
+    partial: Vec<u8>,
+}
 
-impl<'a> Decode<'a> for NoOpDecoder {
-    fn decode_sample(&mut self, buf: &[u8], out: &mut [i16]) -> Result<usize, anyhow::Error> {
-        // SAFETY: This is safe by design - a no-op decoder passes the data through as-is
-        let (_, converted, _) = unsafe { buf.align_to::<i16>() };
-        out[0..converted.len()].copy_from_slice(converted);
+impl PcmDecoder {
+    pub fn new(format: SampleFormat, channels: usize) -> PcmDecoder {
+        let frame_bytes = format.bytes() * channels.max(1);
+        PcmDecoder {
+            format,
+            frame_bytes,
+            partial: Vec::with_capacity(frame_bytes),
+        }
+    }
 
-        Ok(converted.len())
+    fn read(&self, bytes: &[u8], out: &mut [i32]) {
+        for (o, b) in out.iter_mut().zip(bytes.chunks_exact(self.format.bytes())) {
+            *o = self.format.read(b);
+        }
+    }
+}
+
+impl Decode for PcmDecoder {
+    fn decode_sample(&mut self, buf: &[u8], out: &mut [i32]) -> Result<usize, anyhow::Error> {
+        let frames = (self.partial.len() + buf.len()) / self.frame_bytes;
+        let samples = frames * self.frame_bytes / self.format.bytes();
+        anyhow::ensure!(
+            samples <= out.len(),
+            "{samples} samples do not fit in a {} sample buffer",
+            out.len()
+        );
+        let mut buf = buf;
+        let mut n = 0;
+        if !self.partial.is_empty() {
+            let take = (self.frame_bytes - self.partial.len()).min(buf.len());
+            self.partial.extend_from_slice(&buf[..take]);
+            buf = &buf[take..];
+            if self.partial.len() < self.frame_bytes {
+                return Ok(0);
+            }
+            n = self.frame_bytes / self.format.bytes();
+            self.read(&self.partial, out);
+            self.partial.clear();
+        }
+        let whole = buf.len() - buf.len() % self.frame_bytes;
+        self.read(&buf[..whole], &mut out[n..]);
+        self.partial.extend_from_slice(&buf[whole..]);
+        Ok(samples)
+    }
+
+    fn reset(&mut self) -> Result<(), anyhow::Error> {
+        self.partial.clear();
+        Ok(())
     }
 }
 
 #[cfg(feature = "flac")]
 pub struct FlacDecoder {
     dec_buf: Vec<i32>,
+    /// Shifts the stream's samples up to full scale.
+    shift: u32,
+}
+#[cfg(feature = "flac")]
+impl Default for FlacDecoder {
+    fn default() -> FlacDecoder {
+        FlacDecoder::new(16)
+    }
 }
 #[cfg(feature = "flac")]
 impl FlacDecoder {
-    pub fn new() -> FlacDecoder {
+    /// A decoder for a stream of `bits_per_sample`, from its STREAMINFO;
+    /// frames do not always repeat it.
+    pub fn new(bits_per_sample: u16) -> FlacDecoder {
         FlacDecoder {
             dec_buf: Vec::with_capacity(2048),
+            shift: 32 - bits_per_sample.clamp(4, 32) as u32,
         }
     }
 }
 
 #[cfg(feature = "flac")]
-impl<'a> Decode<'a> for FlacDecoder {
-    fn decode_sample(&mut self, buf: &[u8], out: &mut [i16]) -> Result<usize, anyhow::Error> {
+impl Decode for FlacDecoder {
+    fn decode_sample(&mut self, buf: &[u8], out: &mut [i32]) -> Result<usize, anyhow::Error> {
         let mut fr = FrameReader::new(std::io::Cursor::new(buf));
         let mut c = 0;
-        loop {
-            if let Ok(Some(block)) = fr.read_next_or_eof(&mut self.dec_buf) {
-                for (a, b) in block.stereo_samples() {
-                    debug_assert!(a <= i16::MAX as i32);
-                    debug_assert!(a >= i16::MIN as i32);
-                    debug_assert!(b <= i16::MAX as i32);
-                    debug_assert!(b >= i16::MIN as i32);
-                    out[c + 0] = a as i16;
-                    out[c + 1] = b as i16;
-                    c += 2;
+        while let Ok(Some(block)) = fr.read_next_or_eof(&mut self.dec_buf) {
+            let channels = block.channels();
+            let len = (block.duration() * channels) as usize;
+            anyhow::ensure!(
+                c + len <= out.len(),
+                "flac frame overflows the sample buffer"
+            );
+            // blocks hold one channel after the other; interleave them
+            for i in 0..block.duration() {
+                for ch in 0..channels {
+                    out[c] = block.sample(ch, i) << self.shift;
+                    c += 1;
                 }
-            } else {
-                break;
             }
         }
         Ok(c)
     }
 }
+
+/// Vorbis packets out of the Ogg pages snapserver sends, decoded by lewton.
+#[cfg(feature = "vorbis")]
+pub struct VorbisDecoder {
+    // boxed: the headers are large next to the other decoders
+    ident: Box<IdentHeader>,
+    setup: Box<SetupHeader>,
+    /// The overlap carried from one packet into the next; the first packet
+    /// only fills it, and decodes to nothing.
+    pwr: PreviousWindowRight,
+    ogg: OggDemuxer,
+}
+
+#[cfg(feature = "vorbis")]
+impl VorbisDecoder {
+    pub fn new(config: &VorbisMetadata) -> anyhow::Result<VorbisDecoder> {
+        let [ident, _comment, setup] = &config.headers;
+        let ident = read_header_ident(ident).context("vorbis identification header")?;
+        let blocksizes = (ident.blocksize_0, ident.blocksize_1);
+        let setup = read_header_setup(setup, ident.audio_channels, blocksizes)
+            .context("vorbis setup header")?;
+        Ok(VorbisDecoder {
+            ident: Box::new(ident),
+            setup: Box::new(setup),
+            pwr: PreviousWindowRight::new(),
+            ogg: OggDemuxer::new(),
+        })
+    }
+}
+
+#[cfg(feature = "vorbis")]
+impl Decode for VorbisDecoder {
+    fn decode_sample(&mut self, buf: &[u8], out: &mut [i32]) -> Result<usize, anyhow::Error> {
+        let VorbisDecoder {
+            ident,
+            setup,
+            pwr,
+            ogg,
+        } = self;
+        let mut n = 0;
+        ogg.push(buf, |packet| {
+            let pcm: InterleavedSamples<f32> =
+                read_audio_packet_generic(ident, setup, packet, pwr).context("vorbis packet")?;
+            let dst = out
+                .get_mut(n..n + pcm.samples.len())
+                .ok_or_else(|| anyhow::anyhow!("vorbis packet overflows the sample buffer"))?;
+            for (o, &s) in dst.iter_mut().zip(&pcm.samples) {
+                *o = crate::sample::from_float(s as f64);
+            }
+            n += pcm.samples.len();
+            Ok::<_, anyhow::Error>(())
+        })?;
+        Ok(n)
+    }
+
+    fn reset(&mut self) -> Result<(), anyhow::Error> {
+        self.pwr = PreviousWindowRight::new();
+        self.ogg = OggDemuxer::new();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pcm_is_widened_to_full_scale() {
+        let mut out = [0; 4];
+        let mut dec = PcmDecoder::new(SampleFormat::S16, 2);
+        let n = dec
+            .decode_sample(&[0x01, 0x00, 0xff, 0xff], &mut out)
+            .unwrap();
+        assert_eq!(out[..n], [1 << 16, -1 << 16]);
+        let mut dec = PcmDecoder::new(SampleFormat::S24, 2);
+        let n = dec
+            .decode_sample(&[0x01, 0x00, 0x00, 0x00, 0xff, 0xff, 0x7f, 0x00], &mut out)
+            .unwrap();
+        assert_eq!(out[..n], [1 << 8, 0x7fffff << 8]);
+    }
+
+    #[test]
+    fn pcm_frames_split_across_chunks() {
+        let mut dec = PcmDecoder::new(SampleFormat::S16, 2);
+        let stream: Vec<u8> = (1..=6i16).flat_map(|s| s.to_le_bytes()).collect();
+        let mut got = Vec::new();
+        let mut out = [0; 8];
+        // cut mid-sample, then mid-frame
+        for chunk in [&stream[..3], &stream[3..6], &stream[6..]] {
+            let n = dec.decode_sample(chunk, &mut out).unwrap();
+            got.extend(out[..n].iter().map(|s| s >> 16));
+        }
+        assert_eq!(got, [1, 2, 3, 4, 5, 6]);
+
+        // after a reset, a held back partial frame is gone
+        let mut dec = PcmDecoder::new(SampleFormat::S16, 2);
+        assert_eq!(dec.decode_sample(&stream[..3], &mut out).unwrap(), 0);
+        dec.reset().unwrap();
+        assert_eq!(dec.decode_sample(&stream[..4], &mut out).unwrap(), 2);
+        assert_eq!(out[..2], [1 << 16, 2 << 16]);
+    }
+
+    #[cfg(feature = "opus")]
+    #[test]
+    fn unsupported_opus_formats_are_errors() {
+        let opus = |sample_rate, channel_count| OpusMetadata {
+            sample_rate,
+            bit_depth: 16,
+            channel_count,
+        };
+        for (rate, channels) in [(16_000, 1), (24_000, 2), (48_000, 1)] {
+            let mut dec = Decoder::new_opus(&opus(rate, channels)).unwrap();
+            assert!(dec.reset().is_ok());
+        }
+        for (rate, channels) in [(44_100, 2), (48_000, 6), (48_000, 0)] {
+            assert!(Decoder::new_opus(&opus(rate, channels)).is_err());
+        }
+    }
+
+    #[cfg(feature = "vorbis")]
+    #[test]
+    fn broken_vorbis_headers_are_errors() {
+        // stereo, 44.1kHz, blocks of 256 and 2048 samples
+        let mut ident = b"\x01vorbis\0\0\0\0\x02".to_vec();
+        ident.extend_from_slice(&44_100u32.to_le_bytes());
+        ident.extend_from_slice(&[0; 12]);
+        ident.extend_from_slice(&[0xb8, 1]);
+        let config = |setup: &[u8]| VorbisMetadata {
+            sample_rate: 44_100,
+            channel_count: 2,
+            headers: [ident.clone(), b"\x03vorbis".to_vec(), setup.to_vec()],
+            pages: Vec::new(),
+        };
+        let err = Decoder::new_vorbis(&config(b"\x05vorbis\x2a\0"))
+            .err()
+            .expect("a setup header without codebooks decodes");
+        assert!(format!("{err:#}").contains("setup"), "{err:#}");
+        let mut bad_ident = config(b"");
+        bad_ident.headers[0].truncate(20);
+        assert!(Decoder::new_vorbis(&bad_ident).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::proto::Base;
+use std::io::{ErrorKind, Read};
 
 /// What the driver must read next off the socket to advance a protocol machine.
 pub enum Action {
@@ -6,6 +7,16 @@ pub enum Action {
     ReadPacket(u32),
 }
 
+impl Action {
+    /// Bytes the action reads.
+    pub fn size(&self) -> usize {
+        match self {
+            Action::ReadHeader => Base::BASE_SIZE,
+            Action::ReadPacket(size) => *size as usize,
+        }
+    }
+}
+
 /// Bytes the driver read off the socket, fed back into a protocol machine.
 pub enum Event<'a> {
     HeaderReceived(&'a [u8]),
@@ -44,12 +55,131 @@ impl Framing {
     }
 
     /// Consume the header parsed by the preceding [`Framing::on_header`], leaving
-    /// the framing ready for the next header. Panics if no header is pending,
+    /// the framing ready for the next header. Fails if no header is pending,
     /// which can only happen if the driver feeds a packet out of order.
-    pub(crate) fn take_base(&mut self) -> Base {
+    pub(crate) fn take_base(&mut self) -> anyhow::Result<Base> {
         match std::mem::replace(&mut self.state, FramingState::ReadingHeader) {
-            FramingState::ReadingPacket(base) => base,
-            FramingState::ReadingHeader => panic!("PacketReceived without a pending header"),
+            FramingState::ReadingPacket(base) => Ok(base),
+            FramingState::ReadingHeader => anyhow::bail!("PacketReceived without a pending header"),
+        }
+    }
+}
+
+/// Receive buffer for drivers whose reads may stop part-way through a header
+/// or packet (non-blocking sockets, read timeouts, cancelled futures): bytes
+/// already read are kept until the rest arrive.
+pub struct RxBuf {
+    buf: Vec<u8>,
+    filled: usize,
+}
+
+impl Default for RxBuf {
+    fn default() -> RxBuf {
+        RxBuf::new()
+    }
+}
+
+impl RxBuf {
+    pub fn new() -> RxBuf {
+        // pcm data is up to 4880b; flac is up to 9k~
+        RxBuf {
+            buf: vec![0; 9000],
+            filled: 0,
+        }
+    }
+
+    /// The part of a `size`-byte frame still to be read, growing the buffer to
+    /// fit it.
+    pub fn spare(&mut self, size: usize) -> &mut [u8] {
+        if size > self.buf.len() {
+            log::warn!("Resizing pkt buf to {}", size);
+            self.buf.resize(size, 0);
+        }
+        &mut self.buf[self.filled..size]
+    }
+
+    /// Record that `n` bytes were read into [`RxBuf::spare`].
+    pub fn advance(&mut self, n: usize) {
+        self.filled += n;
+    }
+
+    pub fn is_complete(&self, size: usize) -> bool {
+        self.filled >= size
+    }
+
+    /// The completed `size`-byte frame; the next read starts a new one.
+    pub fn take(&mut self, size: usize) -> &[u8] {
+        debug_assert!(self.is_complete(size));
+        self.filled = 0;
+        &self.buf[..size]
+    }
+
+    /// Read from `r` until a `size`-byte frame is complete (`true`) or the
+    /// reader would block or time out (`false`). End of stream is an error.
+    pub fn read_from<R: Read>(&mut self, r: &mut R, size: usize) -> std::io::Result<bool> {
+        while !self.is_complete(size) {
+            match r.read(self.spare(size)) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.advance(n),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(false);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out its data a few bytes at a time, blocking between pieces.
+    struct Dribble<'a> {
+        data: &'a [u8],
+        piece: usize,
+        block_next: bool,
+    }
+
+    impl Read for Dribble<'_> {
+        fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+            self.block_next = !self.block_next;
+            if !self.block_next {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            let n = self.piece.min(out.len()).min(self.data.len());
+            out[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn partial_reads_resume() {
+        let data: Vec<u8> = (0..30).collect();
+        let mut r = Dribble {
+            data: &data,
+            piece: 4,
+            block_next: false,
+        };
+        let mut rx = RxBuf::new();
+        let mut blocked = 0;
+        while !rx.read_from(&mut r, 26).unwrap() {
+            blocked += 1;
         }
+        assert_eq!(blocked, 6);
+        assert_eq!(rx.take(26), &data[..26]);
+        // the next frame starts after the bytes of this one
+        while !rx.read_from(&mut r, 4).unwrap() {}
+        assert_eq!(rx.take(4), &data[26..]);
+        let eof = loop {
+            match rx.read_from(&mut r, 4) {
+                Ok(false) => continue,
+                res => break res,
+            }
+        };
+        assert_eq!(eof.unwrap_err().kind(), ErrorKind::UnexpectedEof);
     }
 }
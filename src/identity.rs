@@ -0,0 +1,86 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Route flag for routes that are up, from `linux/route.h`.
+const RTF_UP: u32 = 0x1;
+
+/// The system hostname, or `None` where neither procfs nor /etc/hostname has one.
+pub fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|p| std::fs::read_to_string(p).ok())
+        .map(|h| h.trim().to_string())
+        .find(|h| !h.is_empty())
+}
+
+/// MAC address of the interface the kernel routes traffic for `dst` through,
+/// as the server sees it in the Hello. IPv6 and unrouted destinations use the
+/// interface of the default route.
+pub fn mac_for(dst: IpAddr) -> Option<String> {
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    let dst = match dst {
+        IpAddr::V4(v4) if !v4.is_loopback() => v4,
+        _ => Ipv4Addr::UNSPECIFIED,
+    };
+    let iface = route_interface(&routes, dst)?;
+    let mac = std::fs::read_to_string(format!("/sys/class/net/{iface}/address")).ok()?;
+    let mac = mac.trim();
+    (!mac.is_empty() && mac != "00:00:00:00:00:00").then(|| mac.to_string())
+}
+
+/// Interface of the most specific up route in a `/proc/net/route` table that
+/// matches `dst`, lowest metric first among equally specific ones.
+fn route_interface(table: &str, dst: Ipv4Addr) -> Option<String> {
+    // addresses and masks are hex in host (little-endian) byte order
+    let dst = u32::from_le_bytes(dst.octets());
+    let hex = |s: &str| u32::from_str_radix(s, 16).ok();
+    let mut best: Option<(u32, u32, &str)> = None;
+    for line in table.lines().skip(1) {
+        let f: Vec<&str> = line.split_whitespace().collect();
+        if f.len() < 8 {
+            continue;
+        }
+        let (Some(dest), Some(flags), Some(metric), Some(mask)) =
+            (hex(f[1]), hex(f[3]), f[6].parse::<u32>().ok(), hex(f[7]))
+        else {
+            continue;
+        };
+        if flags & RTF_UP == 0 || dst & mask != dest {
+            continue;
+        }
+        let prefix = mask.count_ones();
+        let better = match best {
+            None => true,
+            Some((p, m, _)) => prefix > p || (prefix == p && metric < m),
+        };
+        if better {
+            best = Some((prefix, metric, f[0]));
+        }
+    }
+    best.map(|(_, _, iface)| iface.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTES: &str = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t00000000\t0101A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+wlan0\t0001A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+docker0\t000011AC\t00000000\t0000\t0\t0\t0\t0000FFFF\t0\t0\t0
+";
+
+    #[test]
+    fn most_specific_route_wins() {
+        // 192.168.1.20 is on the wlan0 subnet
+        let iface = route_interface(ROUTES, Ipv4Addr::new(192, 168, 1, 20));
+        assert_eq!(iface.as_deref(), Some("wlan0"));
+        // anything else takes the default route with the lowest metric
+        let iface = route_interface(ROUTES, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(iface.as_deref(), Some("eth0"));
+        // routes that are down never match
+        let iface = route_interface(ROUTES, Ipv4Addr::new(172, 17, 0, 2));
+        assert_eq!(iface.as_deref(), Some("eth0"));
+    }
+}
@@ -0,0 +1,268 @@
+use crate::proto::TimeVal;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Holes shorter than this are scheduling noise (chunk lengths are whole
+/// frames, timestamps whole microseconds), not missing audio.
+const MIN_GAP_US: i64 = 1_000;
+
+/// What happens to a chunk whose audible time has already passed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LatePolicy {
+    /// Drop it.
+    Drop,
+    /// Play it if it is at most this late (the sync controller catches up by
+    /// skipping its start), drop it otherwise.
+    Tolerate(Duration),
+}
+
+/// Counters of everything the buffer did besides passing chunks through.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct JitterStats {
+    pub received: u64,
+    pub played: u64,
+    /// Chunks dropped by the [`LatePolicy`].
+    pub expired: u64,
+    /// Chunks dropped because the buffer was full.
+    pub overruns: u64,
+    /// Times the buffer ran dry while audio was playing.
+    pub underruns: u64,
+    /// Holes in the timeline filled with silence.
+    pub gaps: u64,
+}
+
+/// An encoded chunk and when its first sample must be heard, in client time.
+pub struct Chunk {
+    pub audible_at: TimeVal,
+    /// The server's timestamp of its first sample.
+    pub server_ts: TimeVal,
+    pub data: Vec<u8>,
+}
+
+/// What the player should do next, from [`JitterBuffer::pop`].
+pub enum Next {
+    /// Play this chunk, then hand its buffer back with [`JitterBuffer::recycle`].
+    Chunk(Chunk),
+    /// Nothing covers this much of the timeline before the next chunk: play
+    /// silence (or concealment) for it first.
+    Gap(Duration),
+}
+
+/// Bounded, time-ordered queue of chunks between the network and the player.
+/// Payload buffers are recycled, so a steady stream does not allocate.
+pub struct JitterBuffer {
+    /// Audible time, server timestamp and payload, ordered by audible time.
+    queue: VecDeque<(i64, TimeVal, Vec<u8>)>,
+    free: Vec<Vec<u8>>,
+    capacity: usize,
+    late: LatePolicy,
+    /// Where the audio handed to the player so far ends; `None` until playback
+    /// starts and after an underrun.
+    played_until_us: Option<i64>,
+    stats: JitterStats,
+}
+
+impl JitterBuffer {
+    pub fn new(capacity: usize, late: LatePolicy) -> JitterBuffer {
+        let capacity = capacity.max(1);
+        JitterBuffer {
+            queue: VecDeque::with_capacity(capacity),
+            free: Vec::with_capacity(capacity),
+            capacity,
+            late,
+            played_until_us: None,
+            stats: JitterStats::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    /// Queue a copy of `payload`, which the server stamped `server_ts`. When
+    /// full, the oldest chunk is dropped: it is the one closest to being too
+    /// late anyway.
+    pub fn push(&mut self, audible_at: TimeVal, server_ts: TimeVal, payload: &[u8]) {
+        self.stats.received += 1;
+        if self.queue.len() >= self.capacity {
+            if let Some((_, _, data)) = self.queue.pop_front() {
+                self.stats.overruns += 1;
+                self.recycle(data);
+            }
+        }
+        let mut data = self.free.pop().unwrap_or_default();
+        data.clear();
+        data.extend_from_slice(payload);
+        let at = audible_at.to_micros();
+        // chunks nearly always arrive in order, so this is the last slot
+        let pos = self.queue.partition_point(|(t, _, _)| *t <= at);
+        self.queue.insert(pos, (at, server_ts, data));
+    }
+
+    /// The next thing to play at client time `now`, dropping chunks that are
+    /// too late; `None` when nothing is queued.
+    pub fn pop(&mut self, now: TimeVal) -> Option<Next> {
+        let now_us = now.to_micros();
+        let deadline = match self.late {
+            LatePolicy::Drop => now_us,
+            LatePolicy::Tolerate(d) => now_us.saturating_sub(d.as_micros() as i64),
+        };
+        while let Some((at, _, _)) = self.queue.front() {
+            if *at >= deadline {
+                break;
+            }
+            let (_, _, data) = self.queue.pop_front().unwrap();
+            self.stats.expired += 1;
+            self.recycle(data);
+        }
+
+        let Some(&(at, _, _)) = self.queue.front() else {
+            if self.played_until_us.is_some_and(|end| end < now_us) {
+                // everything handed over has been heard: the player ran dry
+                self.stats.underruns += 1;
+                self.played_until_us = None;
+            }
+            return None;
+        };
+        if let Some(end) = self.played_until_us {
+            if at - end >= MIN_GAP_US {
+                self.stats.gaps += 1;
+                self.played_until_us = Some(at);
+                return Some(Next::Gap(Duration::from_micros((at - end) as u64)));
+            }
+        }
+        let (_, server_ts, data) = self.queue.pop_front().unwrap();
+        self.stats.played += 1;
+        Some(Next::Chunk(Chunk {
+            audible_at: TimeVal::from_micros(at),
+            server_ts,
+            data,
+        }))
+    }
+
+    /// Record that the audio handed to the player now ends at `end`, so holes
+    /// before the next chunk are found.
+    pub fn played_until(&mut self, end: TimeVal) {
+        self.played_until_us = Some(end.to_micros());
+    }
+
+    /// Take back the buffer of a played chunk for reuse.
+    pub fn recycle(&mut self, data: Vec<u8>) {
+        if self.free.len() < self.capacity {
+            self.free.push(data);
+        }
+    }
+
+    /// Drop everything queued, e.g. for a new stream; the counters stay.
+    pub fn clear(&mut self) {
+        while let Some((_, _, data)) = self.queue.pop_front() {
+            self.recycle(data);
+        }
+        self.played_until_us = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: i64) -> TimeVal {
+        TimeVal::from_micros(ms * 1000)
+    }
+
+    fn chunk_at(next: Option<Next>) -> i64 {
+        match next {
+            Some(Next::Chunk(c)) => c.audible_at.to_micros() / 1000,
+            Some(Next::Gap(d)) => panic!("unexpected {d:?} gap"),
+            None => panic!("buffer empty"),
+        }
+    }
+
+    #[test]
+    fn chunks_come_out_in_time_order() {
+        let mut jb = JitterBuffer::new(8, LatePolicy::Drop);
+        for t in [40, 20, 60, 0] {
+            jb.push(ms(t), ms(t), &[t as u8]);
+        }
+        for t in [0, 20, 40, 60] {
+            jb.played_until(ms(t));
+            assert_eq!(chunk_at(jb.pop(ms(0))), t);
+        }
+        assert!(jb.pop(ms(0)).is_none());
+    }
+
+    #[test]
+    fn overruns_drop_the_oldest() {
+        let mut jb = JitterBuffer::new(2, LatePolicy::Drop);
+        for t in [0, 20, 40] {
+            jb.push(ms(t), ms(t), &[]);
+        }
+        assert_eq!(jb.stats().overruns, 1);
+        assert_eq!(chunk_at(jb.pop(ms(0))), 20);
+    }
+
+    #[test]
+    fn late_chunks_follow_the_policy() {
+        let mut jb = JitterBuffer::new(8, LatePolicy::Tolerate(Duration::from_millis(30)));
+        for t in [0, 20, 40] {
+            jb.push(ms(t), ms(t), &[]);
+        }
+        // at 45ms, the 0ms chunk is past tolerating; 20ms is late but kept
+        assert_eq!(chunk_at(jb.pop(ms(45))), 20);
+        assert_eq!(jb.stats().expired, 1);
+
+        let mut jb = JitterBuffer::new(8, LatePolicy::Drop);
+        jb.push(ms(0), ms(0), &[]);
+        assert!(jb.pop(ms(1)).is_none());
+        assert_eq!(jb.stats().expired, 1);
+    }
+
+    #[test]
+    fn gaps_and_underruns_are_reported() {
+        let mut jb = JitterBuffer::new(8, LatePolicy::Drop);
+        jb.push(ms(0), ms(0), &[]);
+        assert_eq!(chunk_at(jb.pop(ms(0))), 0);
+        jb.played_until(ms(20));
+        // the 20ms chunk was lost
+        jb.push(ms(40), ms(40), &[]);
+        match jb.pop(ms(0)) {
+            Some(Next::Gap(d)) => assert_eq!(d, Duration::from_millis(20)),
+            _ => panic!("expected a gap"),
+        }
+        assert_eq!(chunk_at(jb.pop(ms(0))), 40);
+        jb.played_until(ms(60));
+
+        // nothing arrives before the player runs out
+        assert!(jb.pop(ms(50)).is_none());
+        assert_eq!(jb.stats().underruns, 0);
+        assert!(jb.pop(ms(70)).is_none());
+        assert!(jb.pop(ms(80)).is_none());
+        let stats = jb.stats();
+        assert_eq!((stats.gaps, stats.underruns), (1, 1));
+    }
+
+    #[test]
+    fn buffers_are_recycled() {
+        let mut jb = JitterBuffer::new(4, LatePolicy::Drop);
+        jb.push(ms(0), ms(0), &[1; 100]);
+        let Some(Next::Chunk(c)) = jb.pop(ms(0)) else {
+            panic!("expected a chunk")
+        };
+        let ptr = c.data.as_ptr();
+        jb.recycle(c.data);
+        jb.push(ms(20), ms(20), &[2; 50]);
+        let Some(Next::Chunk(c)) = jb.pop(ms(0)) else {
+            panic!("expected a chunk")
+        };
+        assert_eq!(c.data.as_ptr(), ptr);
+        assert_eq!(c.data, [2; 50]);
+    }
+}
@@ -1,12 +1,28 @@
+pub mod channels;
 pub mod client;
+pub mod clock;
 #[cfg(feature = "decoder")]
 pub mod decoder;
 pub mod framing;
+pub mod identity;
+pub mod jitter;
 pub mod mdns;
+pub mod nonblocking;
+#[cfg(feature = "vorbis")]
+pub mod ogg;
 #[cfg(feature = "opus")]
 pub use opus_embedded;
 #[cfg(feature = "playback")]
 pub mod playback;
 pub mod proto;
+pub mod reconnect;
+pub mod resample;
+pub mod sample;
 pub mod server;
+#[cfg(all(feature = "decoder", feature = "playback"))]
+pub mod snapclient;
+pub mod sync;
+#[cfg(feature = "tokio")]
+pub mod tokio_client;
+pub mod volume;
 
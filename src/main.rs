@@ -1,23 +1,19 @@
-mod client;
-mod decoder;
-mod framing;
-mod mdns;
-mod playback;
-mod proto;
-
-use client::{Client, Message};
+use snapcast_client::channels::ChannelMap;
+#[cfg(feature = "pipewire")]
+use snapcast_client::playback::PipeWire;
+use snapcast_client::playback::{wav, File, Players, Tcp, Wav};
 #[cfg(feature = "alsa")]
-use playback::Alsa;
+use snapcast_client::playback::{Alsa, AlsaMixer};
 #[cfg(feature = "pulse")]
-use playback::Pulse;
-use playback::{File, Player, Players, Tcp};
-use proto::{CodecHeader, CodecMetadata, TimeVal};
+use snapcast_client::playback::{Pulse, PulseMixer};
+use snapcast_client::proto::CodecHeader;
+use snapcast_client::reconnect::Server;
+use snapcast_client::sample::SampleFormat;
+use snapcast_client::snapclient::{SnapClient, VolumeControl};
+use snapcast_client::volume::VolumeCurve;
 
-use clap::Parser;
-use decoder::{Decode, Decoder};
-
-use std::sync::{mpsc, Arc, Mutex};
-use std::time;
+use clap::{CommandFactory, Parser};
+use std::path::{Path, PathBuf};
 
 #[derive(clap::ValueEnum, Debug, Copy, Clone)]
 enum PlayerBackend {
@@ -25,8 +21,57 @@ enum PlayerBackend {
     Alsa,
     #[cfg(feature = "pulse")]
     Pulse,
-    TCP,
+    #[cfg(feature = "pipewire")]
+    #[value(name = "pipewire")]
+    PipeWire,
+    Tcp,
     File,
+    /// Record to a WAV file, a new one for every stream format.
+    Wav,
+}
+
+impl PlayerBackend {
+    /// Whether there is a volume control for `--mixer hardware` to drive.
+    fn has_mixer(self) -> bool {
+        match self {
+            #[cfg(feature = "alsa")]
+            PlayerBackend::Alsa => true,
+            #[cfg(feature = "pulse")]
+            PlayerBackend::Pulse => true,
+            #[cfg(feature = "pipewire")]
+            PlayerBackend::PipeWire => false,
+            PlayerBackend::Tcp | PlayerBackend::File | PlayerBackend::Wav => false,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq)]
+enum MixerMode {
+    /// Scale the samples before they reach the backend; works with every backend.
+    Software,
+    /// Drive the backend's own volume control (ALSA mixer element, Pulse
+    /// sink-input); the other backends have none.
+    Hardware,
+}
+
+#[derive(clap::ValueEnum, Debug, Copy, Clone)]
+enum OutputFormat {
+    S16,
+    /// 24 bits in the low three bytes of four, as Pulse's s24-32le.
+    S24,
+    S32,
+    F32,
+}
+
+impl From<OutputFormat> for SampleFormat {
+    fn from(f: OutputFormat) -> SampleFormat {
+        match f {
+            OutputFormat::S16 => SampleFormat::S16,
+            OutputFormat::S24 => SampleFormat::S24,
+            OutputFormat::S32 => SampleFormat::S32,
+            OutputFormat::F32 => SampleFormat::F32,
+        }
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -37,124 +82,180 @@ struct Args {
     /// Server address; if omitted, discovered over mDNS (_snapcast._tcp).
     #[arg(short, long)]
     server: Option<String>,
+
+    /// Where the server's volume and mute are applied.
+    #[arg(long, value_enum, default_value_t = MixerMode::Software)]
+    mixer: MixerMode,
+
+    /// How the 0-100 volume maps onto the output level: linear, log or db:<min>:<max>.
+    #[arg(long, default_value = "log")]
+    volume_curve: VolumeCurve,
+
+    /// Which channels to play: auto, mono, stereo, left, right or channel:<n>.
+    /// `left` and `right` make one half of a stereo pair, and play mono streams
+    /// as they are.
+    #[arg(long, default_value = "auto")]
+    channels: ChannelMap,
+
+    /// ALSA control device (e.g. `hw:1`) whose mixer `--mixer hardware` drives.
+    #[arg(long, default_value = "default")]
+    mixer_card: String,
+
+    /// ALSA simple mixer element driven by `--mixer hardware`.
+    #[arg(long, default_value = "Master")]
+    mixer_element: String,
+
+    /// PipeWire node (name or object.serial) to play to; defaults to where the
+    /// session manager routes music.
+    #[cfg(feature = "pipewire")]
+    #[arg(long)]
+    pipewire_target: Option<String>,
+
+    /// File the file and wav backends write to; `out.pcm` and `out.wav` by
+    /// default. Later recordings of the wav backend are numbered: out-1.wav...
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Sample format the file, tcp and wav backends write, little-endian;
+    /// s16 by default, or for wav the one that records the stream exactly.
+    #[arg(long, value_enum)]
+    sample_format: Option<OutputFormat>,
+
+    /// With the wav backend, also write a CSV next to each recording mapping
+    /// the server timestamp of every chunk to the frame it starts at.
+    #[arg(long)]
+    timestamps: bool,
+
+    /// MAC reported to the server; defaults to the interface that reaches it.
+    #[arg(long)]
+    mac: Option<String>,
+
+    /// Hostname reported to the server; defaults to the system hostname.
+    #[arg(long)]
+    hostname: Option<String>,
+
+    /// ID the server keeps this client's settings under; defaults to the MAC.
+    #[arg(long)]
+    id: Option<String>,
+
+    /// Instance number, to run several clients on one host.
+    #[arg(long, default_value_t = 1)]
+    instance: u8,
+
+    /// Client name shown by the server.
+    #[arg(long, default_value = "CoolClient")]
+    client_name: String,
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    if args.mixer == MixerMode::Hardware && !args.backend.has_mixer() {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--mixer hardware needs the alsa or pulse backend",
+            )
+            .exit();
+    }
 
-    let server = match args.server {
-        Some(s) => s,
-        None => mdns::discover("_snapcast._tcp.local", time::Duration::from_secs(3))?
-            .ok_or_else(|| anyhow::anyhow!("no _snapcast._tcp server found on the network"))?
-            .to_string(),
+    let server = match args.server.clone() {
+        Some(s) => Server::Addr(s),
+        None => Server::Discover("_snapcast._tcp.local".into()),
+    };
+    let volume_control = match args.mixer {
+        MixerMode::Software => VolumeControl::Software,
+        MixerMode::Hardware => VolumeControl::Hardware,
     };
-    println!("connecting to {server}");
-
-    let client = Client::new("11:22:33:44:55:66".into(), "framework".into());
-    let mut client = client.connect(server.as_str())?;
-    let time_base_c = client.time_base();
-
-    let dec: Arc<Mutex<Option<Decoder>>> = Arc::new(Mutex::new(None));
-    let dec_2 = dec.clone();
-
-    let player: Arc<Mutex<Option<Players>>> = Arc::new(Mutex::new(None));
-    let player_2 = player.clone();
-
-    let (sample_tx, sample_rx) = mpsc::channel::<(TimeVal, Vec<u8>)>();
-    std::thread::spawn(move || handle_samples(sample_rx, time_base_c, player, dec));
-
-    loop {
-        let in_sync = client.synchronized();
-        let msg = client.tick()?;
-        match msg {
-            Message::CodecHeader(ch) => {
-                #[allow(unreachable_patterns)]
-                let d = match &ch.metadata {
-                    CodecMetadata::Pcm(_) => Decoder::new_pcm(),
-                    #[cfg(feature = "flac")]
-                    CodecMetadata::Flac(_) => Decoder::new_flac(),
-                    #[cfg(feature = "opus")]
-                    CodecMetadata::Opus(cfg) => {
-                        Decoder::new_opus(cfg, Box::leak(Box::new_uninit()))?
-                    }
-                    other => anyhow::bail!("codec disabled at build time: {other:?}"),
-                };
-                _ = dec_2.lock().unwrap().insert(d);
-                let p = make_player(args.backend, &ch)?;
-                _ = player_2.lock().unwrap().insert(p);
-            }
-            Message::WireChunk(wc, audible_at) => {
-                // before the offset buffer fills, audible_at is computed from a
-                // bogus clock offset; forwarding those would schedule playback
-                // wildly in the future
-                if in_sync {
-                    sample_tx.send((audible_at, wc.payload.to_vec()))?;
-                }
-            }
 
-            Message::ServerSettings(_v) => {
-                // TODO volume
-            }
-            _ => (),
-        }
+    let mut builder = SnapClient::builder(server)
+        .with_instance(args.instance)
+        .with_client_name(&args.client_name);
+    if let Some(mac) = &args.mac {
+        builder = builder.with_mac(mac);
     }
-}
-
-fn handle_samples(
-    sample_rx: mpsc::Receiver<(TimeVal, Vec<u8>)>,
-    time_base_c: time::Instant,
-    player: Arc<Mutex<Option<Players>>>,
-    dec: Arc<Mutex<Option<Decoder>>>,
-) {
-    // >= (960 * 2) for OPUS
-    // >= 2880 for PCM
-    // >= 4600 for FLAC
-    let mut samples_out = vec![0; 4700];
-
-    let mut player_lat_ms: u16 = 1;
-    while let Ok((client_audible_ts, samples)) = sample_rx.recv() {
-        let remaining = client_audible_ts - time_base_c.elapsed().into();
-        if remaining.sec < 0 {
-            println!("aaa in the past");
-            continue;
-        }
-
-        // sleep until the chunk is due, leaving the player's own latency of lead
-        let remaining_us = remaining.to_micros();
-        let lead_us = player_lat_ms as i64 * 1000;
-        if remaining_us > lead_us {
-            std::thread::sleep(time::Duration::from_micros((remaining_us - lead_us) as u64));
-        }
-
-        // Guard against chunks coming before the decoder is initialized
-        let Some(ref mut dec) = *dec.lock().unwrap() else {
-            continue;
-        };
-        let Some(ref mut p) = *player.lock().unwrap() else {
-            continue;
-        };
-        // Backends with 0ms of buffer (file, tcp) otherwise behave erratically
-        player_lat_ms = std::cmp::max(1, p.latency_ms().unwrap());
-        let decoded_sample_c = dec.decode_sample(&samples, &mut samples_out).unwrap();
-        let mut sample = &mut samples_out[0..decoded_sample_c];
-        p.play().unwrap();
-        p.write(&mut sample).unwrap();
+    if let Some(hostname) = &args.hostname {
+        builder = builder.with_hostname(hostname);
     }
+    if let Some(id) = &args.id {
+        builder = builder.with_id(id);
+    }
+
+    // each stream format is recorded to a file of its own
+    let mut recordings = 0;
+    builder
+        .with_volume_control(volume_control)
+        .with_volume_curve(args.volume_curve)
+        .with_channel_map(args.channels)
+        .on_stream_tags(|t| {
+            let title = t.title.as_deref().unwrap_or("?");
+            let artist = t.artist.as_deref().unwrap_or("?");
+            println!("now playing: {artist} - {title}");
+        })
+        .on_error(|e| println!("{e:#}"))
+        .with_player(move |ch| {
+            let p = make_player(&args, ch, recordings);
+            recordings += 1;
+            p
+        })
+        .build()?
+        .run()
 }
 
-fn make_player(b: PlayerBackend, ch: &CodecHeader) -> anyhow::Result<Players> {
-    match b {
+/// The player for a stream; `recording` counts the players made before.
+fn make_player(args: &Args, ch: &CodecHeader, recording: usize) -> anyhow::Result<Players> {
+    let (rate, channels) = (ch.metadata.rate(), ch.metadata.channels());
+    let channels = args.channels.channels(channels);
+    let raw_format = args
+        .sample_format
+        .map_or(SampleFormat::S16, SampleFormat::from);
+    match args.backend {
         #[cfg(feature = "alsa")]
-        PlayerBackend::Alsa => Ok(Players::from(Alsa::new(ch.metadata.rate())?)),
+        PlayerBackend::Alsa => {
+            let mut p = Alsa::new(rate, channels)?;
+            if args.mixer == MixerMode::Hardware {
+                let m = AlsaMixer::new(&args.mixer_card, &args.mixer_element, args.volume_curve)?;
+                p = p.with_mixer(m);
+            }
+            Ok(Players::from(p))
+        }
         #[cfg(feature = "pulse")]
-        PlayerBackend::Pulse => Ok(Players::from(Pulse::new(ch.metadata.rate())?)),
-        PlayerBackend::TCP => Ok(Players::from(Tcp::new(
-            "127.0.0.1:12345",
-            ch.metadata.rate(),
-        )?)),
-        PlayerBackend::File => Ok(Players::from(File::new(
-            std::path::Path::new("out.pcm"),
-            ch.metadata.rate(),
+        PlayerBackend::Pulse => {
+            let mut p = Pulse::new(rate, channels)?;
+            if args.mixer == MixerMode::Hardware {
+                p = p.with_mixer(PulseMixer::new(args.volume_curve)?);
+            }
+            Ok(Players::from(p))
+        }
+        #[cfg(feature = "pipewire")]
+        PlayerBackend::PipeWire => Ok(Players::from(PipeWire::new(
+            args.pipewire_target.as_deref(),
+            rate,
+            channels,
         )?)),
+        PlayerBackend::Tcp => Ok(Players::from(
+            Tcp::new("127.0.0.1:12345", rate, channels)?.with_format(raw_format),
+        )),
+        PlayerBackend::File => Ok(Players::from(
+            File::new(
+                args.output.as_deref().unwrap_or(Path::new("out.pcm")),
+                rate,
+                channels,
+            )?
+            .with_format(raw_format),
+        )),
+        PlayerBackend::Wav => {
+            let path = wav::numbered(
+                args.output.as_deref().unwrap_or(Path::new("out.wav")),
+                recording,
+            );
+            let format = args
+                .sample_format
+                .map_or(ch.metadata.sample_format(), SampleFormat::from);
+            let mut p = Wav::new(&path, rate, channels)?.with_format(format);
+            if args.timestamps {
+                p = p.with_timestamps(&path.with_extension("csv"))?;
+            }
+            Ok(Players::from(p))
+        }
     }
 }
@@ -0,0 +1,194 @@
+use crate::client::{Action, Client, ClientMachine, Event, Message, MAX_TRANSMIT};
+use crate::framing::RxBuf;
+use crate::proto::TimeVal;
+use std::io::{ErrorKind, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// Non-blocking shell around [`ClientMachine`] for single-threaded poll loops.
+/// The caller waits until the socket is readable (and writable, while
+/// [`NonBlockingClient::wants_write`]) or [`NonBlockingClient::timeout`] passes,
+/// then calls [`NonBlockingClient::poll`] until it returns [`Message::Nothing`].
+/// Frames that arrive in pieces are reassembled across calls.
+pub struct NonBlockingClient {
+    conn: TcpStream,
+    time_base: Instant,
+    machine: ClientMachine,
+    rx: RxBuf,
+    /// Bytes the socket has not accepted yet.
+    tx: Vec<u8>,
+    tx_buf: [u8; MAX_TRANSMIT],
+}
+
+impl Client {
+    /// Connect and send the Hello like [`Client::connect`], then switch the
+    /// socket to non-blocking mode.
+    pub fn connect_nonblocking<A: ToSocketAddrs>(
+        &self,
+        dst: A,
+    ) -> anyhow::Result<NonBlockingClient> {
+        let mut conn = TcpStream::connect(dst)?;
+        if let Err(e) = conn.set_nodelay(true) {
+            log::error!("Failed to set nodelay on connection: {:?}", e);
+        }
+        conn.write_all(&self.hello(conn.peer_addr().ok()))?;
+        conn.set_nonblocking(true)?;
+        Ok(NonBlockingClient {
+            conn,
+            time_base: Instant::now(),
+            machine: ClientMachine::new(),
+            rx: RxBuf::new(),
+            tx: Vec::with_capacity(MAX_TRANSMIT),
+            tx_buf: [0; MAX_TRANSMIT],
+        })
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for NonBlockingClient {
+    /// The socket, for registering with poll(2), epoll or mio's `SourceFd`.
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.conn.as_raw_fd()
+    }
+}
+
+impl NonBlockingClient {
+    fn now_us(&self) -> i64 {
+        self.time_base.elapsed().as_micros() as i64
+    }
+
+    pub fn synchronized(&self) -> bool {
+        self.machine.synchronized()
+    }
+
+    /// Server-to-client clock offset predicted at the last Time exchange.
+    pub fn clock_offset(&self) -> TimeVal {
+        self.machine.clock_offset()
+    }
+
+    pub fn time_base(&self) -> Instant {
+        self.time_base
+    }
+
+    /// Report a local volume/mute change to the server on the next `poll`.
+    pub fn send_client_info(&mut self, volume: u8, muted: bool) {
+        self.machine.send_client_info(volume, muted);
+    }
+
+    /// Output is queued that the socket did not accept; wait for it to become
+    /// writable as well.
+    pub fn wants_write(&self) -> bool {
+        !self.tx.is_empty()
+    }
+
+    /// How long the caller may wait on the socket before the next Time request
+    /// is due.
+    pub fn timeout(&self) -> Duration {
+        let due_us = self
+            .machine
+            .next_transmit_us()
+            .saturating_sub(self.now_us());
+        Duration::from_micros(due_us.max(0) as u64)
+    }
+
+    /// Do the I/O that is possible without blocking: send what is due, then
+    /// return the next message, or [`Message::Nothing`] once no complete frame
+    /// is left to read.
+    pub fn poll(&mut self) -> anyhow::Result<Message<'_>> {
+        self.flush()?;
+        loop {
+            let action = self.machine.next_action();
+            let size = action.size();
+            if !self.rx.read_from(&mut self.conn, size)? {
+                return Ok(Message::Nothing);
+            }
+            let rx_now = self.now_us();
+            if let Action::ReadPacket(_) = action {
+                let ev = Event::PacketReceived(self.rx.take(size));
+                return self.machine.handle_event(ev, rx_now);
+            }
+            let ev = Event::HeaderReceived(self.rx.take(size));
+            // returns Nothing and transitions to ReadPacket; loop to read it
+            self.machine.handle_event(ev, rx_now)?;
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        // a request queued behind unsent bytes would carry a stale timestamp
+        if self.tx.is_empty() {
+            let now = self.now_us();
+            while let Some(n) = self.machine.poll_transmit(now, &mut self.tx_buf) {
+                self.tx.extend_from_slice(&self.tx_buf[..n]);
+            }
+        }
+        while !self.tx.is_empty() {
+            match self.conn.write(&self.tx) {
+                Ok(0) => anyhow::bail!("server closed the connection"),
+                Ok(n) => {
+                    self.tx.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::OwnedMessage;
+    use crate::proto::{Base, ServerSettings};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn frames_split_across_polls_are_reassembled() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = ServerSettings {
+            bufferMs: 1000,
+            latency: 0,
+            muted: false,
+            volume: 80,
+        };
+        let frame = settings.as_buf(0, TimeVal { sec: 0, usec: 0 });
+        let frame_len = frame.len();
+        // the server sends the frame up to each offset it is given
+        let (upto_tx, upto_rx) = mpsc::channel::<usize>();
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut sent = 0;
+            for upto in upto_rx {
+                conn.write_all(&frame[sent..upto]).unwrap();
+                sent = upto;
+            }
+            conn
+        });
+
+        let client = Client::new("00:11:22:33:44:55".into(), "test".into());
+        let mut c = client.connect_nonblocking(addr).unwrap();
+        // part of the header, then the rest of it and part of the payload
+        for upto in [10, Base::BASE_SIZE + 3] {
+            upto_tx.send(upto).unwrap();
+            thread::sleep(Duration::from_millis(20));
+            assert!(matches!(c.poll().unwrap(), Message::Nothing));
+        }
+        upto_tx.send(frame_len).unwrap();
+        let mut got = None;
+        for _ in 0..100 {
+            thread::sleep(Duration::from_millis(10));
+            if let Some(msg) = c.poll().unwrap().into_owned() {
+                got = Some(msg);
+                break;
+            }
+        }
+        assert_eq!(got, Some(OwnedMessage::ServerSettings(settings)));
+
+        drop(upto_tx);
+        drop(server.join().unwrap());
+    }
+}
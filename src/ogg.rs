@@ -0,0 +1,198 @@
+use crate::proto::ProtoError;
+
+const PAGE_HEADER: usize = 27;
+/// Header type flag: the page starts with the rest of the previous page's last
+/// packet.
+const CONTINUED: u8 = 0x01;
+
+/// Continue the CRC-32 of Ogg pages over `data`: polynomial 0x04c11db7, not
+/// reflected, starting from 0, no final xor.
+fn crc(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc ^= (b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Splits whole Ogg pages into the packets they carry, as snapserver sends
+/// them for Vorbis: the codec header holds the pages of the three header
+/// packets, each chunk the pages of its audio packets. A packet continued on
+/// the next page is held until it completes.
+#[derive(Default)]
+pub struct OggDemuxer {
+    /// The start of a packet that continues on the next page.
+    partial: Vec<u8>,
+}
+
+impl OggDemuxer {
+    pub fn new() -> OggDemuxer {
+        OggDemuxer::default()
+    }
+
+    /// Call `f` with every packet completed by the pages in `buf`, which must
+    /// hold whole pages.
+    pub fn push<E: From<ProtoError>>(
+        &mut self,
+        mut buf: &[u8],
+        mut f: impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        while !buf.is_empty() {
+            let (page, rest) = split_page(buf)?;
+            buf = rest;
+            let segments = page[PAGE_HEADER - 1] as usize;
+            let (lacing, mut body) = page[PAGE_HEADER..].split_at(segments);
+            let continued = page[5] & CONTINUED != 0;
+            if !continued {
+                // a packet cut short, by a page that was never sent
+                self.partial.clear();
+            }
+            // the end of a packet whose start was never seen, e.g. when
+            // joining mid-stream, is dropped
+            let mut orphan = continued && self.partial.is_empty();
+
+            // lacing values of 255 continue a packet, anything less ends it
+            let mut len = 0;
+            for (i, &lace) in lacing.iter().enumerate() {
+                len += lace as usize;
+                if lace == 255 && i + 1 < segments {
+                    continue;
+                }
+                let (packet, rest) = body.split_at(len);
+                body = rest;
+                len = 0;
+                if std::mem::take(&mut orphan) {
+                    continue;
+                }
+                if lace == 255 {
+                    self.partial.extend_from_slice(packet);
+                } else if self.partial.is_empty() {
+                    f(packet)?;
+                } else {
+                    self.partial.extend_from_slice(packet);
+                    let done = f(&self.partial);
+                    self.partial.clear();
+                    done?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The page at the start of `buf` and what follows it.
+fn split_page(buf: &[u8]) -> Result<(&[u8], &[u8]), ProtoError> {
+    let header = buf
+        .get(..PAGE_HEADER)
+        .ok_or(ProtoError::Truncated("ogg page header"))?;
+    if header[0..4] != *b"OggS" {
+        return Err(ProtoError::Malformed(
+            "ogg page: missing OggS capture pattern",
+        ));
+    }
+    if header[4] != 0 {
+        return Err(ProtoError::Malformed("ogg page: unknown version"));
+    }
+    let segments = header[PAGE_HEADER - 1] as usize;
+    let lacing = buf
+        .get(PAGE_HEADER..PAGE_HEADER + segments)
+        .ok_or(ProtoError::Truncated("ogg lacing values"))?;
+    let len = PAGE_HEADER + segments + lacing.iter().map(|&l| l as usize).sum::<usize>();
+    if buf.len() < len {
+        return Err(ProtoError::Truncated("ogg page"));
+    }
+    let (page, rest) = buf.split_at(len);
+    // the checksum is computed with its own field zeroed
+    let expected = u32::from_le_bytes(page[22..26].try_into().unwrap());
+    let mut header = [0u8; PAGE_HEADER];
+    header.copy_from_slice(&page[..PAGE_HEADER]);
+    header[22..26].fill(0);
+    if crc(crc(0, &header), &page[PAGE_HEADER..]) != expected {
+        return Err(ProtoError::Malformed("ogg page: bad checksum"));
+    }
+    Ok((page, rest))
+}
+
+/// Wrap `packets` into one page, for tests; a packet ending in a lacing value
+/// of 255 is continued on the next page when `last_continues` is set.
+#[cfg(test)]
+pub(crate) fn page(packets: &[&[u8]], continued: bool, last_continues: bool) -> Vec<u8> {
+    let mut lacing = Vec::new();
+    for (i, p) in packets.iter().enumerate() {
+        lacing.extend(std::iter::repeat_n(255, p.len() / 255));
+        if !(last_continues && i + 1 == packets.len()) {
+            lacing.push((p.len() % 255) as u8);
+        }
+    }
+    let mut page = b"OggS\0".to_vec();
+    page.push(if continued { CONTINUED } else { 0 });
+    page.extend_from_slice(&[0; 20]);
+    page.push(lacing.len() as u8);
+    page.extend_from_slice(&lacing);
+    for p in packets {
+        page.extend_from_slice(p);
+    }
+    let sum = crc(0, &page);
+    page[22..26].copy_from_slice(&sum.to_le_bytes());
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packets(d: &mut OggDemuxer, buf: &[u8]) -> Result<Vec<Vec<u8>>, ProtoError> {
+        let mut got = Vec::new();
+        d.push(buf, |p| {
+            got.push(p.to_vec());
+            Ok::<_, ProtoError>(())
+        })?;
+        Ok(got)
+    }
+
+    #[test]
+    fn packets_come_out_of_pages() {
+        let big = [7u8; 600];
+        let mut buf = page(&[b"one", &big], false, false);
+        buf.extend(page(&[&[]], false, false));
+        let got = packets(&mut OggDemuxer::new(), &buf).unwrap();
+        assert_eq!(got, [b"one".to_vec(), big.to_vec(), vec![]]);
+    }
+
+    #[test]
+    fn packets_continue_across_pages() {
+        let mut d = OggDemuxer::new();
+        // 510 bytes end a page on a lacing value of 255
+        let first = page(&[b"a", &[1; 510]], false, true);
+        assert_eq!(packets(&mut d, &first).unwrap(), [b"a".to_vec()]);
+        let second = page(&[&[2; 3], b"b"], true, false);
+        let mut joined = vec![1; 510];
+        joined.extend_from_slice(&[2; 3]);
+        assert_eq!(packets(&mut d, &second).unwrap(), [joined, b"b".to_vec()]);
+        // without the first page, the rest of its packet is dropped
+        let got = packets(&mut OggDemuxer::new(), &second).unwrap();
+        assert_eq!(got, [b"b".to_vec()]);
+    }
+
+    #[test]
+    fn damaged_pages_are_errors() {
+        let good = page(&[b"packet"], false, false);
+        let mut d = OggDemuxer::new();
+        for end in 1..good.len() {
+            assert!(packets(&mut d, &good[..end]).is_err());
+        }
+        let mut flipped = good.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            packets(&mut d, &flipped),
+            Err(ProtoError::Malformed(_))
+        ));
+        assert_eq!(packets(&mut d, &good).unwrap(), [b"packet".to_vec()]);
+    }
+}
@@ -1,28 +1,95 @@
+use alsa::mixer::{MilliBel, Mixer, SelemId};
 use alsa::pcm::{Access, Format, HwParams, State, PCM};
-use alsa::{Direction, ValueOr};
+use alsa::{Direction, Round, ValueOr};
 
 use super::Player;
+use crate::sample::{SampleEncoder, SampleFormat};
+use crate::volume::VolumeCurve;
 
 pub struct Alsa {
     pcm: PCM,
     buf_time_ms: u16,
-    sample_rate: u16,
+    sample_rate: u32,
+    channels: usize,
+    enc: SampleEncoder,
+    mixer: Option<AlsaMixer>,
+}
+
+/// A simple mixer element (e.g. `Master` on card `default`) driven by
+/// [`Player::set_volume`]. Attenuating in the DAC keeps the full sample
+/// resolution that a software gain throws away at low volume.
+pub struct AlsaMixer {
+    mixer: Mixer,
+    selem: SelemId,
+    curve: VolumeCurve,
+}
+
+impl AlsaMixer {
+    pub fn new(card: &str, element: &str, curve: VolumeCurve) -> anyhow::Result<AlsaMixer> {
+        let mixer = Mixer::new(card, false)?;
+        let selem = SelemId::new(element, 0);
+        let elem = mixer
+            .find_selem(&selem)
+            .ok_or_else(|| anyhow::anyhow!("no mixer element {element:?} on {card:?}"))?;
+        anyhow::ensure!(
+            elem.has_playback_volume(),
+            "mixer element {element:?} has no playback volume"
+        );
+        Ok(AlsaMixer {
+            mixer,
+            selem,
+            curve,
+        })
+    }
+
+    fn set(&self, volume: u8) -> anyhow::Result<()> {
+        let elem = self
+            .mixer
+            .find_selem(&self.selem)
+            .ok_or_else(|| anyhow::anyhow!("mixer element disappeared"))?;
+        if elem.has_playback_switch() {
+            elem.set_playback_switch_all((volume > 0) as i32)?;
+        }
+        let (min, max) = elem.get_playback_db_range();
+        if min < max {
+            let db = self
+                .curve
+                .db(volume, min.to_db(), max.to_db())
+                .unwrap_or(min.to_db());
+            elem.set_playback_db_all(MilliBel::from_db(db), Round::Floor)?;
+        } else {
+            // no dB information: spread the curve's gain over the raw steps
+            let (min, max) = elem.get_playback_volume_range();
+            let raw = min + ((max - min) as f32 * self.curve.gain(volume)).round() as i64;
+            elem.set_playback_volume_all(raw)?;
+        }
+        Ok(())
+    }
 }
 
 impl Alsa {
-    pub fn new(rate: usize) -> anyhow::Result<Alsa> {
+    pub fn new(rate: usize, channels: usize) -> anyhow::Result<Alsa> {
         // Open default playback device
         let pcm = PCM::new("default", Direction::Playback, false)?;
 
         // going below this gets no audio on my device
         let req_bufsize = 300;
 
-        let buf_time_us = {
-            // Set hardware parameters: 48000 Hz / Stereo / 16 bit
+        let (buf_time_us, format, rate) = {
+            // Set hardware parameters, in the widest sample format the device
+            // takes, so hi-res streams keep their resolution
             let hwp = HwParams::any(&pcm)?;
-            hwp.set_channels(2)?;
+            hwp.set_channels(channels as u32)?;
             hwp.set_rate(rate as u32, ValueOr::Nearest)?;
-            hwp.set_format(Format::s16())?;
+            let (alsa_format, format) = [
+                (Format::s32(), SampleFormat::S32),
+                (Format::s24(), SampleFormat::S24),
+                (Format::s16(), SampleFormat::S16),
+            ]
+            .into_iter()
+            .find(|(f, _)| hwp.test_format(*f).is_ok())
+            .ok_or_else(|| anyhow::anyhow!("device takes none of S32, S24 or S16"))?;
+            hwp.set_format(alsa_format)?;
             hwp.set_access(Access::RWInterleaved)?;
             hwp.set_buffer_size(req_bufsize)?;
             hwp.set_period_size(req_bufsize / 4, alsa::ValueOr::Nearest)?;
@@ -32,21 +99,32 @@ impl Alsa {
             // Copied from synth example
             let (bufsize, periodsize) = (hwp.get_buffer_size()?, hwp.get_period_size()?);
             let hwp = pcm.hw_params_current()?;
+            // the nearest rate the device does, resampled to by the client
+            let rate = hwp.get_rate()?;
             let swp = pcm.sw_params_current()?;
             swp.set_start_threshold(bufsize - periodsize)?;
             swp.set_avail_min(periodsize)?;
             pcm.sw_params(&swp)?;
 
             // us as defined in https://www.alsa-project.org/alsa-doc/alsa-lib/group___p_c_m___h_w___params.html#gaa18c9999c27632f6c47e163b6af17fa9
-            (hwp.get_buffer_time_min()? + hwp.get_buffer_time_max()?) / 2
+            let buf_time_us = (hwp.get_buffer_time_min()? + hwp.get_buffer_time_max()?) / 2;
+            (buf_time_us, format, rate)
         };
 
         Ok(Alsa {
             pcm,
             buf_time_ms: (buf_time_us / 1000) as u16,
-            sample_rate: rate as u16,
+            sample_rate: rate,
+            channels,
+            enc: SampleEncoder::new(format),
+            mixer: None,
         })
     }
+
+    pub fn with_mixer(mut self, mixer: AlsaMixer) -> Alsa {
+        self.mixer = Some(mixer);
+        self
+    }
 }
 
 impl Player for Alsa {
@@ -56,19 +134,39 @@ impl Player for Alsa {
         }
         Ok(())
     }
-    fn write(&mut self, buf: &mut [i16]) -> anyhow::Result<()> {
-        let io = self.pcm.io_i16()?;
-        io.writei(buf)?;
+    fn write(&mut self, buf: &[i32]) -> anyhow::Result<()> {
+        let io = self.pcm.io_bytes();
+        io.writei(self.enc.encode(buf))?;
         Ok(())
     }
     fn latency_ms(&self) -> anyhow::Result<u16> {
         Ok(self.buf_time_ms)
     }
-    fn set_volume(&mut self, _val: u8) -> anyhow::Result<()> {
-        println!("setting volume is not implemented in alsa backend");
-        Ok(())
+    fn delay_us(&self) -> anyhow::Result<i64> {
+        let frames = self.pcm.delay()?;
+        Ok(frames * 1_000_000 / self.sample_rate as i64)
+    }
+    fn set_volume(&mut self, val: u8) -> anyhow::Result<()> {
+        match &self.mixer {
+            Some(m) => m.set(val),
+            None => anyhow::bail!("alsa backend opened without a mixer element"),
+        }
     }
-    fn sample_rate(&self) -> u16 {
+    fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
+    fn sample_format(&self) -> SampleFormat {
+        self.enc.format()
+    }
+    fn channels(&self) -> usize {
+        self.channels
+    }
+    fn close(&mut self) -> anyhow::Result<()> {
+        // a stream that never started (or underran) has nothing left to play
+        if self.pcm.state() == State::Running {
+            self.pcm.drain()?;
+        }
+        self.pcm.drop()?;
+        Ok(())
+    }
 }
@@ -1,37 +1,56 @@
 use crate::playback::Player;
+use crate::sample::{SampleEncoder, SampleFormat};
 use std::io::Write;
 
 pub struct File {
     f: std::fs::File,
-    sample_rate: u16,
+    sample_rate: u32,
+    channels: usize,
+    enc: SampleEncoder,
 }
 impl Player for File {
     fn play(&mut self) -> anyhow::Result<()> {
         Ok(())
     }
-    fn write(&mut self, buf: &mut [i16]) -> anyhow::Result<()> {
-        // SAFETY: it's always safe to align i16 to u8
-        let (_, converted, _) = unsafe { buf.align_to::<u8>() };
-        self.f.write_all(converted)?;
+    fn write(&mut self, buf: &[i32]) -> anyhow::Result<()> {
+        self.f.write_all(self.enc.encode(buf))?;
         Ok(())
     }
     fn latency_ms(&self) -> anyhow::Result<u16> {
         Ok(0)
     }
     fn set_volume(&mut self, _val: u8) -> anyhow::Result<()> {
-        // ?
-        Ok(())
+        anyhow::bail!("the file backend has no hardware mixer")
     }
-    fn sample_rate(&self) -> u16 {
+    fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
+    fn sample_format(&self) -> SampleFormat {
+        self.enc.format()
+    }
+    fn channels(&self) -> usize {
+        self.channels
+    }
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.f.flush()?;
+        Ok(())
+    }
 }
 
 impl File {
-    pub fn new(p: &std::path::Path, rate: usize) -> anyhow::Result<File> {
+    pub fn new(p: &std::path::Path, rate: usize, channels: usize) -> anyhow::Result<File> {
         Ok(File {
             f: std::fs::File::create(p)?,
-            sample_rate: rate as u16,
+            sample_rate: rate as u32,
+            channels,
+            enc: SampleEncoder::new(SampleFormat::S16),
         })
     }
+
+    /// Write the samples as `format` rather than 16-bit; the file has no
+    /// header, so whatever reads it must be told.
+    pub fn with_format(mut self, format: SampleFormat) -> File {
+        self.enc = SampleEncoder::new(format);
+        self
+    }
 }
@@ -1,28 +1,63 @@
 #[cfg(feature = "alsa")]
 pub mod alsa;
 #[cfg(feature = "alsa")]
-pub use alsa::Alsa;
+pub use alsa::{Alsa, AlsaMixer};
 
 #[cfg(feature = "pulse")]
-pub use pulse::Pulse;
+pub use pulse::{Pulse, PulseMixer};
 #[cfg(feature = "pulse")]
 pub mod pulse;
 
+#[cfg(feature = "pipewire")]
+pub mod pipewire;
+#[cfg(feature = "pipewire")]
+pub use pipewire::PipeWire;
+
 pub mod file;
 pub use file::File;
 
 pub mod tcp;
 pub use tcp::Tcp;
 
+pub mod wav;
+pub use wav::Wav;
+
+use crate::proto::TimeVal;
+use crate::sample::SampleFormat;
 use enum_dispatch::enum_dispatch;
 
 #[enum_dispatch]
 pub trait Player {
     fn play(&mut self) -> anyhow::Result<()>;
-    fn write(&mut self, buf: &mut [i16]) -> anyhow::Result<()>;
+    /// Write full-scale samples (see [`crate::sample::SampleEncoder`]),
+    /// converted to the backend's [`Player::sample_format`].
+    fn write(&mut self, buf: &[i32]) -> anyhow::Result<()>;
     fn latency_ms(&self) -> anyhow::Result<u16>;
+    /// How long until a sample written now is heard, in microseconds. Backends
+    /// that can query their queue fill should; the default assumes a full
+    /// buffer.
+    fn delay_us(&self) -> anyhow::Result<i64> {
+        Ok(self.latency_ms()? as i64 * 1000)
+    }
+    /// Drive the backend's own volume control with a snapcast volume (0-100);
+    /// 0 is silence. Backends without one return an error.
     fn set_volume(&mut self, val: u8) -> anyhow::Result<()>;
-    fn sample_rate(&self) -> u16;
+    /// The rate the backend actually runs at, which may differ from the one
+    /// asked for; the client resamples to it.
+    fn sample_rate(&self) -> u32;
+    fn sample_format(&self) -> SampleFormat;
+    /// Channels the backend was opened with; streams with fewer are upmixed.
+    fn channels(&self) -> usize;
+    /// The next sample written is the one the server stamped `server_ts`.
+    /// Called before each chunk's samples; backends that record the stream's
+    /// timing keep it.
+    fn mark_server_time(&mut self, _server_ts: TimeVal) {}
+    /// Stop and let go of the device, before the player is dropped for a new
+    /// one; nothing is written afterwards. Backends with short buffers play
+    /// out what was written, others drop it so the next stream starts soon.
+    fn close(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 #[enum_dispatch(Player)]
@@ -31,6 +66,9 @@ pub enum Players {
     Alsa,
     #[cfg(feature = "pulse")]
     Pulse,
+    #[cfg(feature = "pipewire")]
+    PipeWire,
     File,
     Tcp,
+    Wav,
 }
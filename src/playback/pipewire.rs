@@ -0,0 +1,504 @@
+use pipewire as pw;
+use pw::channel::{Receiver as PwReceiver, Sender as PwSender};
+use pw::spa;
+use pw::spa::param::audio::{AudioFormat, AudioInfoRaw, MAX_CHANNELS};
+use pw::spa::pod::serialize::PodSerializer;
+use pw::spa::pod::{Object, Pod, Value};
+use pw::stream::{Stream, StreamFlags, StreamRef, StreamState};
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::Player;
+use crate::sample::{SampleEncoder, SampleFormat};
+
+const APP_NAME: &str = "snapcast-client";
+/// Audio queued between `write` and the stream's process callback.
+const BUFFER_MS: usize = 100;
+/// The graph quantum asked for; the graph may run another.
+const QUANTUM_MS: usize = 10;
+/// How long `write` waits for room before giving up on a stream that stopped
+/// pulling, e.g. one that was never linked to a device.
+const STALL: Duration = Duration::from_secs(1);
+
+/// Plays through a native PipeWire stream. The stream pulls audio from its
+/// process callback on a thread of its own, which owns every PipeWire object
+/// (none of them are `Send`); `write` queues samples for it and blocks while
+/// the queue is full.
+pub struct PipeWire {
+    shared: Arc<Shared>,
+    quit: PwSender<()>,
+    thread: Option<JoinHandle<()>>,
+    /// Queue size in bytes, whole frames.
+    capacity: usize,
+    sample_rate: u32,
+    channels: usize,
+    enc: SampleEncoder,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Signalled when the process callback takes from the queue, and when the
+    /// stream fails.
+    room: Condvar,
+}
+
+#[derive(Default)]
+struct Queue {
+    bytes: VecDeque<u8>,
+    /// From the stream's time info at the last process callback: how long
+    /// until what it handed over is heard.
+    stream_delay_us: i64,
+    /// Why the stream stopped, once it has.
+    error: Option<String>,
+}
+
+impl Queue {
+    /// Move up to `requested` frames (as many as fit when 0) into `out`,
+    /// padded with silence when the queue runs dry; returns the bytes filled.
+    fn take(&mut self, out: &mut [u8], frame: usize, requested: usize) -> usize {
+        let mut frames = out.len() / frame;
+        if requested > 0 {
+            frames = frames.min(requested);
+        }
+        let len = frames * frame;
+        let n = len.min(self.bytes.len());
+        for (o, b) in out[..n].iter_mut().zip(self.bytes.drain(..n)) {
+            *o = b;
+        }
+        out[n..len].fill(0);
+        len
+    }
+}
+
+impl Shared {
+    fn fail(&self, error: String) {
+        self.queue.lock().unwrap().error.get_or_insert(error);
+        self.room.notify_all();
+    }
+
+    /// The process callback: hand the stream the frames it asked for from
+    /// the queue, padded with silence when the queue runs dry.
+    fn fill(&self, stream: &StreamRef, frame: usize, rate: u32) {
+        let Some(mut buffer) = stream.dequeue_buffer() else {
+            return;
+        };
+        let requested = buffer.requested() as usize;
+        let Some(data) = buffer.datas_mut().first_mut() else {
+            return;
+        };
+        let Some(out) = data.data() else {
+            return;
+        };
+        let mut q = self.queue.lock().unwrap();
+        let len = q.take(out, frame, requested);
+        q.stream_delay_us = stream_delay_us(stream, rate);
+        drop(q);
+        self.room.notify_all();
+
+        let chunk = data.chunk_mut();
+        *chunk.offset_mut() = 0;
+        *chunk.stride_mut() = frame as i32;
+        *chunk.size_mut() = len as u32;
+    }
+}
+
+/// How long until audio handed to the stream now is heard, from its time
+/// info: the graph's delay to the device, plus what the stream's own
+/// converter still holds.
+fn stream_delay_us(stream: &StreamRef, rate: u32) -> i64 {
+    // SAFETY: pw_time is plain integers, for which zero is valid, and the
+    // stream fills no more than the size passed
+    let (res, time) = unsafe {
+        let mut time: pw::sys::pw_time = std::mem::zeroed();
+        let res = pw::sys::pw_stream_get_time_n(
+            stream.as_raw_ptr(),
+            &mut time,
+            std::mem::size_of::<pw::sys::pw_time>(),
+        );
+        (res, time)
+    };
+    if res < 0 || time.rate.denom == 0 {
+        return 0;
+    }
+    let graph = time.delay * 1_000_000 * time.rate.num as i64 / time.rate.denom as i64;
+    graph + time.buffered as i64 * 1_000_000 / rate as i64
+}
+
+/// Speaker positions for `channels` channels in the order the client plays
+/// them (see [`crate::channels::ChannelMap`]); layouts without one are
+/// auxiliary channels.
+fn positions(channels: usize) -> [u32; MAX_CHANNELS] {
+    use spa::sys::*;
+    let layout: &[u32] = match channels {
+        1 => &[SPA_AUDIO_CHANNEL_MONO],
+        2 => &[SPA_AUDIO_CHANNEL_FL, SPA_AUDIO_CHANNEL_FR],
+        3 => &[
+            SPA_AUDIO_CHANNEL_FL,
+            SPA_AUDIO_CHANNEL_FR,
+            SPA_AUDIO_CHANNEL_FC,
+        ],
+        4 => &[
+            SPA_AUDIO_CHANNEL_FL,
+            SPA_AUDIO_CHANNEL_FR,
+            SPA_AUDIO_CHANNEL_RL,
+            SPA_AUDIO_CHANNEL_RR,
+        ],
+        5 => &[
+            SPA_AUDIO_CHANNEL_FL,
+            SPA_AUDIO_CHANNEL_FR,
+            SPA_AUDIO_CHANNEL_FC,
+            SPA_AUDIO_CHANNEL_RL,
+            SPA_AUDIO_CHANNEL_RR,
+        ],
+        6 => &[
+            SPA_AUDIO_CHANNEL_FL,
+            SPA_AUDIO_CHANNEL_FR,
+            SPA_AUDIO_CHANNEL_FC,
+            SPA_AUDIO_CHANNEL_LFE,
+            SPA_AUDIO_CHANNEL_RL,
+            SPA_AUDIO_CHANNEL_RR,
+        ],
+        7 => &[
+            SPA_AUDIO_CHANNEL_FL,
+            SPA_AUDIO_CHANNEL_FR,
+            SPA_AUDIO_CHANNEL_FC,
+            SPA_AUDIO_CHANNEL_LFE,
+            SPA_AUDIO_CHANNEL_RC,
+            SPA_AUDIO_CHANNEL_SL,
+            SPA_AUDIO_CHANNEL_SR,
+        ],
+        8 => &[
+            SPA_AUDIO_CHANNEL_FL,
+            SPA_AUDIO_CHANNEL_FR,
+            SPA_AUDIO_CHANNEL_FC,
+            SPA_AUDIO_CHANNEL_LFE,
+            SPA_AUDIO_CHANNEL_RL,
+            SPA_AUDIO_CHANNEL_RR,
+            SPA_AUDIO_CHANNEL_SL,
+            SPA_AUDIO_CHANNEL_SR,
+        ],
+        _ => &[],
+    };
+    let mut position = [SPA_AUDIO_CHANNEL_UNKNOWN; MAX_CHANNELS];
+    for (c, p) in position[..channels].iter_mut().enumerate() {
+        *p = layout
+            .get(c)
+            .copied()
+            .unwrap_or(SPA_AUDIO_CHANNEL_AUX0 + c as u32);
+    }
+    position
+}
+
+/// The EnumFormat param offering exactly the stream's format; PipeWire
+/// converts to whatever the device takes.
+fn format_param(rate: u32, channels: usize) -> anyhow::Result<Vec<u8>> {
+    let mut info = AudioInfoRaw::new();
+    info.set_format(AudioFormat::S32LE);
+    info.set_rate(rate);
+    info.set_channels(channels as u32);
+    info.set_position(positions(channels));
+    let (cursor, _) = PodSerializer::serialize(
+        Cursor::new(Vec::new()),
+        &Value::Object(Object {
+            type_: spa::sys::SPA_TYPE_OBJECT_Format,
+            id: spa::sys::SPA_PARAM_EnumFormat,
+            properties: info.into(),
+        }),
+    )
+    .map_err(|e| anyhow::anyhow!("serializing the pipewire format: {e:?}"))?;
+    Ok(cursor.into_inner())
+}
+
+/// Everything the stream thread needs to open the stream.
+struct StreamConfig {
+    target: Option<String>,
+    rate: u32,
+    channels: usize,
+    frame: usize,
+    shared: Arc<Shared>,
+}
+
+impl StreamConfig {
+    /// Open the stream and run its loop until `quit`; `ready` hears whether
+    /// the stream was connected.
+    fn run(self, quit: PwReceiver<()>, ready: &Sender<anyhow::Result<()>>) -> anyhow::Result<()> {
+        let mainloop = pw::main_loop::MainLoop::new(None)?;
+        let context = pw::context::Context::new(&mainloop)?;
+        let core = context.connect(None)?;
+
+        let quantum = self.rate as usize * QUANTUM_MS / 1000;
+        let mut props = pw::properties::properties! {
+            *pw::keys::MEDIA_TYPE => "Audio",
+            *pw::keys::MEDIA_CATEGORY => "Playback",
+            *pw::keys::MEDIA_ROLE => "Music",
+            *pw::keys::APP_NAME => APP_NAME,
+            *pw::keys::NODE_LATENCY => format!("{quantum}/{}", self.rate),
+        };
+        if let Some(target) = &self.target {
+            props.insert(*pw::keys::TARGET_OBJECT, target.as_str());
+        }
+        let stream = Stream::new(&core, "Music", props)?;
+
+        let (frame, rate) = (self.frame, self.rate);
+        let _listener = stream
+            .add_local_listener_with_user_data(self.shared.clone())
+            .state_changed(|_, shared, _, new| match new {
+                StreamState::Error(e) => shared.fail(e),
+                StreamState::Unconnected => shared.fail("disconnected".into()),
+                _ => {}
+            })
+            .process(move |stream, shared| shared.fill(stream, frame, rate))
+            .register()?;
+
+        let format = format_param(self.rate, self.channels)?;
+        let mut params = [
+            Pod::from_bytes(&format).ok_or_else(|| anyhow::anyhow!("invalid pipewire format"))?
+        ];
+        stream.connect(
+            spa::utils::Direction::Output,
+            None,
+            StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+            &mut params,
+        )?;
+
+        let ml = mainloop.clone();
+        let _quit = quit.attach(mainloop.loop_(), move |()| ml.quit());
+        let _ = ready.send(Ok(()));
+        mainloop.run();
+        let _ = stream.disconnect();
+        Ok(())
+    }
+}
+
+impl PipeWire {
+    /// Open a stream to `target`, a node name or `object.serial`, or to
+    /// wherever the session manager routes music when it is `None`.
+    pub fn new(target: Option<&str>, rate: usize, channels: usize) -> anyhow::Result<PipeWire> {
+        anyhow::ensure!(
+            (1..=MAX_CHANNELS).contains(&channels),
+            "pipewire cannot play {channels} channels"
+        );
+        let enc = SampleEncoder::new(SampleFormat::S32);
+        let frame = enc.format().bytes() * channels;
+        let shared = Arc::new(Shared::default());
+        let config = StreamConfig {
+            target: target.map(str::to_owned),
+            rate: rate as u32,
+            channels,
+            frame,
+            shared: shared.clone(),
+        };
+
+        let (quit, quit_rx) = pw::channel::channel();
+        let (ready_tx, ready_rx) = channel();
+        let thread = std::thread::spawn(move || {
+            if let Err(e) = config.run(quit_rx, &ready_tx) {
+                let _ = ready_tx.send(Err(e));
+            }
+        });
+        ready_rx.recv()??;
+
+        Ok(PipeWire {
+            shared,
+            quit,
+            thread: Some(thread),
+            capacity: rate * BUFFER_MS / 1000 * frame,
+            sample_rate: rate as u32,
+            channels,
+            enc,
+        })
+    }
+
+    /// Stop the stream's loop, which disconnects the stream.
+    fn stop(&mut self) -> anyhow::Result<()> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        let _ = self.quit.send(());
+        thread
+            .join()
+            .map_err(|_| anyhow::anyhow!("pipewire thread panicked"))
+    }
+}
+
+impl Drop for PipeWire {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+impl Player for PipeWire {
+    fn play(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn write(&mut self, buf: &[i32]) -> anyhow::Result<()> {
+        let mut rest = self.enc.encode(buf);
+        let mut q = self.shared.queue.lock().unwrap();
+        while !rest.is_empty() {
+            if let Some(e) = &q.error {
+                anyhow::bail!("pipewire stream stopped: {e}");
+            }
+            let room = self.capacity.saturating_sub(q.bytes.len());
+            if room == 0 {
+                let (guard, wait) = self.shared.room.wait_timeout(q, STALL).unwrap();
+                q = guard;
+                anyhow::ensure!(
+                    !wait.timed_out(),
+                    "pipewire stream is not playing; is it linked to a device?"
+                );
+                continue;
+            }
+            let n = room.min(rest.len());
+            q.bytes.extend(&rest[..n]);
+            rest = &rest[n..];
+        }
+        Ok(())
+    }
+    fn latency_ms(&self) -> anyhow::Result<u16> {
+        Ok((self.delay_us()? / 1000) as u16)
+    }
+    fn delay_us(&self) -> anyhow::Result<i64> {
+        let q = self.shared.queue.lock().unwrap();
+        let frames = (q.bytes.len() / (self.enc.format().bytes() * self.channels)) as i64;
+        Ok(frames * 1_000_000 / self.sample_rate as i64 + q.stream_delay_us)
+    }
+    fn set_volume(&mut self, _val: u8) -> anyhow::Result<()> {
+        anyhow::bail!("pipewire backend has no mixer")
+    }
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    fn sample_format(&self) -> SampleFormat {
+        self.enc.format()
+    }
+    fn channels(&self) -> usize {
+        self.channels
+    }
+    fn close(&mut self) -> anyhow::Result<()> {
+        // the queue is short: play it out, unless the stream stopped pulling
+        let q = self.shared.queue.lock().unwrap();
+        let timeout = Duration::from_millis(2 * BUFFER_MS as u64);
+        drop(
+            self.shared
+                .room
+                .wait_timeout_while(q, timeout, |q| !q.bytes.is_empty() && q.error.is_none())
+                .unwrap(),
+        );
+        self.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pw::spa::pod::deserialize::PodDeserializer;
+    use pw::spa::pod::ValueArray;
+    use pw::spa::utils::Id;
+    use spa::sys::*;
+
+    /// A player with no stream behind it, for the queue side of the backend.
+    fn player(rate: usize, channels: usize) -> PipeWire {
+        let (quit, _) = pw::channel::channel();
+        let enc = SampleEncoder::new(SampleFormat::S32);
+        let frame = enc.format().bytes() * channels;
+        PipeWire {
+            shared: Arc::default(),
+            quit,
+            thread: None,
+            capacity: rate * BUFFER_MS / 1000 * frame,
+            sample_rate: rate as u32,
+            channels,
+            enc,
+        }
+    }
+
+    #[test]
+    fn speaker_positions() {
+        let p = positions(2);
+        assert_eq!(p[..2], [SPA_AUDIO_CHANNEL_FL, SPA_AUDIO_CHANNEL_FR]);
+        assert!(p[2..].iter().all(|&c| c == SPA_AUDIO_CHANNEL_UNKNOWN));
+        assert_eq!(positions(1)[0], SPA_AUDIO_CHANNEL_MONO);
+        assert_eq!(positions(6)[3], SPA_AUDIO_CHANNEL_LFE);
+        // no layout for ten channels: all auxiliary
+        let p = positions(10);
+        assert!((0..10).all(|c| p[c] == SPA_AUDIO_CHANNEL_AUX0 + c as u32));
+        assert_eq!(p[10], SPA_AUDIO_CHANNEL_UNKNOWN);
+    }
+
+    #[test]
+    fn format_offers_the_stream_format() {
+        let bytes = format_param(44_100, 2).unwrap();
+        let (_, value) = PodDeserializer::deserialize_any_from(&bytes).unwrap();
+        let Value::Object(object) = &value else {
+            panic!("not an object: {value:?}");
+        };
+        assert_eq!(object.type_, SPA_TYPE_OBJECT_Format);
+        assert_eq!(object.id, SPA_PARAM_EnumFormat);
+        let prop = |key| {
+            let p = object.properties.iter().find(|p| p.key == key);
+            p.map(|p| p.value.clone())
+        };
+        assert_eq!(
+            prop(SPA_FORMAT_AUDIO_format),
+            Some(Value::Id(Id(SPA_AUDIO_FORMAT_S32_LE)))
+        );
+        assert_eq!(prop(SPA_FORMAT_AUDIO_rate), Some(Value::Int(44_100)));
+        assert_eq!(prop(SPA_FORMAT_AUDIO_channels), Some(Value::Int(2)));
+        assert_eq!(
+            prop(SPA_FORMAT_AUDIO_position),
+            Some(Value::ValueArray(ValueArray::Id(vec![
+                Id(SPA_AUDIO_CHANNEL_FL),
+                Id(SPA_AUDIO_CHANNEL_FR)
+            ])))
+        );
+    }
+
+    #[test]
+    fn take_pads_with_silence() {
+        let mut q = Queue::default();
+        q.bytes.extend(1..=12u8);
+        // a buffer of 4 four-byte frames, of which the stream wants 3
+        let mut out = [0xff; 16];
+        assert_eq!(q.take(&mut out, 4, 3), 12);
+        assert_eq!(out[..12], (1..=12).collect::<Vec<u8>>()[..]);
+        assert_eq!(out[12..], [0xff; 4]);
+        // a dry queue plays silence for as much as the buffer holds
+        assert_eq!(q.take(&mut out, 4, 0), 16);
+        assert_eq!(out, [0; 16]);
+
+        q.bytes.extend(1..=8u8);
+        assert_eq!(q.take(&mut out, 4, 3), 12);
+        assert_eq!(out[..12], [1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0]);
+        // partial frames at the end of the buffer are left alone
+        let mut out = [0xff; 6];
+        q.bytes.extend(1..=8u8);
+        assert_eq!(q.take(&mut out, 4, 0), 4);
+        assert_eq!(out, [1, 2, 3, 4, 0xff, 0xff]);
+        assert_eq!(q.bytes, [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn write_queues_and_counts_delay() {
+        let mut p = player(48_000, 2);
+        p.write(&[0; 20]).unwrap();
+        assert_eq!(p.shared.queue.lock().unwrap().bytes.len(), 80);
+        p.shared.queue.lock().unwrap().stream_delay_us = 5_000;
+        // 10 frames at 48kHz, plus the stream's own delay
+        assert_eq!(p.delay_us().unwrap(), 208 + 5_000);
+    }
+
+    #[test]
+    fn write_reports_the_first_failure() {
+        let mut p = player(48_000, 2);
+        p.shared.fail("node went away".into());
+        p.shared.fail("disconnected".into());
+        let e = p.write(&[0; 2]).unwrap_err().to_string();
+        assert!(e.contains("node went away"), "{e}");
+        assert!(p.shared.queue.lock().unwrap().bytes.is_empty());
+    }
+}
@@ -1,54 +1,237 @@
 use super::Player;
+use crate::sample::{SampleEncoder, SampleFormat};
+use crate::volume::VolumeCurve;
+use libpulse_binding::callbacks::ListResult;
+use libpulse_binding::channelmap::{Map, MapDef};
+use libpulse_binding::context::{Context, FlagSet as ContextFlags, State as ContextState};
+use libpulse_binding::mainloop::threaded::Mainloop;
+use libpulse_binding::operation::{Operation, State as OperationState};
+use libpulse_binding::proplist::properties::APPLICATION_PROCESS_ID;
 use libpulse_binding::sample::{Format, Spec};
 use libpulse_binding::stream::Direction;
+use libpulse_binding::volume::{ChannelVolumes, Volume, VolumeLinear};
 use libpulse_simple_binding::Simple;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const APP_NAME: &str = "FooApp";
 
 pub struct Pulse {
     pulse: Simple,
-    sample_rate: u16,
+    sample_rate: u32,
+    channels: usize,
+    enc: SampleEncoder,
+    mixer: Option<PulseMixer>,
+}
+
+/// Sets the volume of our own sink-input. The simple API has no volume control,
+/// so this keeps a separate context on its own thread (the context is not
+/// `Send`) and finds the stream by our process id.
+pub struct PulseMixer {
+    tx: Sender<u8>,
+}
+
+impl PulseMixer {
+    pub fn new(curve: VolumeCurve) -> anyhow::Result<PulseMixer> {
+        let (tx, rx) = channel();
+        let (ready_tx, ready_rx) = channel();
+        std::thread::spawn(move || match MixerContext::connect() {
+            Ok(mut ctx) => {
+                let _ = ready_tx.send(Ok(()));
+                ctx.run(rx, curve);
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+            }
+        });
+        ready_rx.recv()??;
+        Ok(PulseMixer { tx })
+    }
+
+    fn set(&self, volume: u8) -> anyhow::Result<()> {
+        self.tx
+            .send(volume)
+            .map_err(|_| anyhow::anyhow!("pulse mixer thread exited"))
+    }
+}
+
+struct MixerContext {
+    mainloop: Mainloop,
+    context: Context,
+}
+
+impl MixerContext {
+    fn connect() -> anyhow::Result<MixerContext> {
+        let mut mainloop =
+            Mainloop::new().ok_or_else(|| anyhow::anyhow!("creating pulse mainloop"))?;
+        let mut context = Context::new(&mainloop, APP_NAME)
+            .ok_or_else(|| anyhow::anyhow!("creating pulse context"))?;
+        context.connect(None, ContextFlags::NOFLAGS, None)?;
+        mainloop.start()?;
+        loop {
+            mainloop.lock();
+            let state = context.get_state();
+            mainloop.unlock();
+            match state {
+                ContextState::Ready => return Ok(MixerContext { mainloop, context }),
+                ContextState::Failed | ContextState::Terminated => {
+                    anyhow::bail!("pulse context failed to connect")
+                }
+                _ => std::thread::sleep(Duration::from_millis(5)),
+            }
+        }
+    }
+
+    fn run(&mut self, rx: Receiver<u8>, curve: VolumeCurve) {
+        while let Ok(mut volume) = rx.recv() {
+            // only the latest value matters when a slider is dragged
+            while let Ok(v) = rx.try_recv() {
+                volume = v;
+            }
+            if let Err(e) = self.set_volume(volume, curve) {
+                log::warn!("setting pulse volume: {e}");
+            }
+        }
+        self.mainloop.lock();
+        self.context.disconnect();
+        self.mainloop.unlock();
+        self.mainloop.stop();
+    }
+
+    fn set_volume(&mut self, volume: u8, curve: VolumeCurve) -> anyhow::Result<()> {
+        let (index, channels) = self
+            .own_sink_input()?
+            .ok_or_else(|| anyhow::anyhow!("no sink-input for this process"))?;
+        let level = match curve.gain(volume) {
+            g if g > 0.0 => Volume::from(VolumeLinear(g as f64)),
+            _ => Volume::MUTED,
+        };
+        let mut cv = ChannelVolumes::default();
+        cv.set(channels, level);
+
+        self.mainloop.lock();
+        let op = self
+            .context
+            .introspect()
+            .set_sink_input_volume(index, &cv, None);
+        self.mainloop.unlock();
+        self.wait(op);
+        Ok(())
+    }
+
+    fn own_sink_input(&mut self) -> anyhow::Result<Option<(u32, u8)>> {
+        let pid = std::process::id().to_string();
+        let found = Arc::new(Mutex::new(None));
+        let found_cb = found.clone();
+
+        self.mainloop.lock();
+        let op = self
+            .context
+            .introspect()
+            .get_sink_input_info_list(move |res| {
+                if let ListResult::Item(info) = res {
+                    if info.proplist.get_str(APPLICATION_PROCESS_ID).as_deref()
+                        == Some(pid.as_str())
+                    {
+                        *found_cb.lock().unwrap() = Some((info.index, info.volume.len()));
+                    }
+                }
+            });
+        self.mainloop.unlock();
+        self.wait(op);
+
+        let found = *found.lock().unwrap();
+        Ok(found)
+    }
+
+    /// Poll until `op` completes; the callbacks run on the mainloop thread.
+    fn wait<F: ?Sized>(&mut self, op: Operation<F>) {
+        loop {
+            self.mainloop.lock();
+            let state = op.get_state();
+            self.mainloop.unlock();
+            if state != OperationState::Running {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        self.mainloop.lock();
+        drop(op);
+        self.mainloop.unlock();
+    }
 }
 
 impl Pulse {
-    pub fn new(rate: usize) -> anyhow::Result<Pulse> {
+    pub fn new(rate: usize, channels: usize) -> anyhow::Result<Pulse> {
         let spec = Spec {
-            format: Format::S16NE,
-            channels: 2,
+            // pulse converts to whatever the sink takes
+            format: Format::S32le,
+            channels: channels as u8,
             rate: rate as u32,
         };
+        anyhow::ensure!(
+            spec.is_valid(),
+            "pulse cannot play {channels} channels at {rate}Hz"
+        );
+        // streams come in WAV channel order
+        let mut map = Map::default();
+        map.init_extend(spec.channels, MapDef::WAVEEx);
         let pulse = Simple::new(
             None,                // Use the default server
-            "FooApp",            // Our application’s name
+            APP_NAME,            // Our application’s name
             Direction::Playback, // We want a playback stream
             None,                // Use the default device
             "Music",             // Description of our stream
             &spec,               // Our sample format
-            None,                // Use default channel map
+            Some(&map),          // WAV channel order
             None,                // Use default buffering attributes
         )?;
 
         Ok(Pulse {
             pulse,
-            sample_rate: rate as u16,
+            sample_rate: rate as u32,
+            channels,
+            enc: SampleEncoder::new(SampleFormat::S32),
+            mixer: None,
         })
     }
+
+    pub fn with_mixer(mut self, mixer: PulseMixer) -> Pulse {
+        self.mixer = Some(mixer);
+        self
+    }
 }
 impl Player for Pulse {
     fn play(&mut self) -> anyhow::Result<()> {
         Ok(())
     }
-    fn write(&mut self, buf: &mut [i16]) -> anyhow::Result<()> {
-        // SAFETY: it's always safe to align i16 to u8
-        let (_, converted, _) = unsafe { buf.align_to::<u8>() };
-        Ok(self.pulse.write(converted)?)
+    fn write(&mut self, buf: &[i32]) -> anyhow::Result<()> {
+        Ok(self.pulse.write(self.enc.encode(buf))?)
     }
     fn latency_ms(&self) -> anyhow::Result<u16> {
         Ok(self.pulse.get_latency()?.as_millis() as u16)
     }
-    fn set_volume(&mut self, _val: u8) -> anyhow::Result<()> {
-        println!("setting volume is not implemented in pulse backend");
-        Ok(())
+    fn delay_us(&self) -> anyhow::Result<i64> {
+        Ok(self.pulse.get_latency()?.0 as i64)
     }
-    fn sample_rate(&self) -> u16 {
+    fn set_volume(&mut self, val: u8) -> anyhow::Result<()> {
+        match &self.mixer {
+            Some(m) => m.set(val),
+            None => anyhow::bail!("pulse backend opened without a mixer"),
+        }
+    }
+    fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
+    fn sample_format(&self) -> SampleFormat {
+        self.enc.format()
+    }
+    fn channels(&self) -> usize {
+        self.channels
+    }
+    fn close(&mut self) -> anyhow::Result<()> {
+        // pulse buffers seconds; playing them out would delay the next stream
+        Ok(self.pulse.flush()?)
+    }
 }
@@ -1,20 +1,21 @@
 use crate::playback::Player;
+use crate::sample::{SampleEncoder, SampleFormat};
 use std::io::Write;
-use std::net::{TcpStream, ToSocketAddrs};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
 
 pub struct Tcp {
     s: TcpStream,
-    sample_rate: u16,
+    sample_rate: u32,
+    channels: usize,
+    enc: SampleEncoder,
 }
 
 impl Player for Tcp {
     fn play(&mut self) -> anyhow::Result<()> {
         Ok(())
     }
-    fn write(&mut self, buf: &mut [i16]) -> anyhow::Result<()> {
-        // SAFETY: it's always safe to align i16 to u8
-        let (_, converted, _) = unsafe { buf.align_to::<u8>() };
-        self.s.write_all(converted)?;
+    fn write(&mut self, buf: &[i32]) -> anyhow::Result<()> {
+        self.s.write_all(self.enc.encode(buf))?;
         Ok(())
     }
 
@@ -23,20 +24,39 @@ impl Player for Tcp {
         Ok(0)
     }
     fn set_volume(&mut self, _val: u8) -> anyhow::Result<()> {
-        // ?
-        Ok(())
+        anyhow::bail!("the tcp backend has no hardware mixer")
     }
-    fn sample_rate(&self) -> u16 {
+    fn sample_rate(&self) -> u32 {
         self.sample_rate
     }
+    fn sample_format(&self) -> SampleFormat {
+        self.enc.format()
+    }
+    fn channels(&self) -> usize {
+        self.channels
+    }
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.s.flush()?;
+        self.s.shutdown(Shutdown::Write)?;
+        Ok(())
+    }
 }
 
 impl Tcp {
-    pub fn new<A: ToSocketAddrs>(addr: A, rate: usize) -> anyhow::Result<Tcp> {
+    pub fn new<A: ToSocketAddrs>(addr: A, rate: usize, channels: usize) -> anyhow::Result<Tcp> {
         let s = TcpStream::connect(addr)?;
         Ok(Tcp {
             s,
-            sample_rate: rate as u16,
+            sample_rate: rate as u32,
+            channels,
+            enc: SampleEncoder::new(SampleFormat::S16),
         })
     }
+
+    /// Send `format` samples rather than 16-bit, to match the `format=` the
+    /// receiving end (e.g. Pulse's simple protocol module) was loaded with.
+    pub fn with_format(mut self, format: SampleFormat) -> Tcp {
+        self.enc = SampleEncoder::new(format);
+        self
+    }
 }
@@ -0,0 +1,300 @@
+use crate::playback::Player;
+use crate::proto::TimeVal;
+use crate::sample::{SampleEncoder, SampleFormat};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// WAVE format tags, from mmreg.h.
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+/// The KSDATAFORMAT_SUBTYPE GUID after its leading format tag.
+const SUBTYPE_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// Records the stream to a WAV file. The header's sizes are filled in when
+/// the player is closed or dropped. With [`Wav::with_timestamps`], a CSV
+/// sidecar maps the server timestamp of every chunk to the frame it starts
+/// at, so recordings from several rooms can be lined up offline.
+/// There is no FLAC counterpart: WAV at the stream's own format already
+/// records it exactly, and compressing would take an encoder dependency.
+pub struct Wav {
+    f: BufWriter<File>,
+    timestamps: Option<BufWriter<File>>,
+    sample_rate: u32,
+    channels: usize,
+    enc: SampleEncoder,
+    /// Bytes of samples written; `None` until the header is.
+    data_len: Option<u64>,
+}
+
+impl Wav {
+    pub fn new(path: &Path, rate: usize, channels: usize) -> anyhow::Result<Wav> {
+        anyhow::ensure!(channels > 0, "a recording needs at least one channel");
+        Ok(Wav {
+            f: BufWriter::new(File::create(path)?),
+            timestamps: None,
+            sample_rate: rate as u32,
+            channels,
+            enc: SampleEncoder::new(SampleFormat::S16),
+            data_len: None,
+        })
+    }
+
+    /// Record samples in `format` instead of 16-bit. 24 bits in 4 bytes are
+    /// written left-justified, as 32-bit, which is how WAV stores them.
+    pub fn with_format(mut self, format: SampleFormat) -> Wav {
+        let format = match format {
+            SampleFormat::S24 => SampleFormat::S32,
+            f => f,
+        };
+        self.enc = SampleEncoder::new(format);
+        self
+    }
+
+    /// Also write `path`, a CSV of each chunk's server timestamp (in
+    /// microseconds) and the frame of the recording it starts at.
+    pub fn with_timestamps(mut self, path: &Path) -> anyhow::Result<Wav> {
+        let mut f = BufWriter::new(File::create(path)?);
+        writeln!(f, "server_time_us,frame")?;
+        self.timestamps = Some(f);
+        Ok(self)
+    }
+
+    fn frame_bytes(&self) -> usize {
+        self.enc.format().bytes() * self.channels
+    }
+
+    /// The RIFF header up to the start of the samples, for `data_len` bytes
+    /// of them. Sizes past 4GiB saturate; readers take those as "to the end".
+    fn header(&self, data_len: u64) -> Vec<u8> {
+        let format = self.enc.format();
+        let tag = match format {
+            SampleFormat::F32 | SampleFormat::F64 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM,
+        };
+        let bits = format.bytes() as u16 * 8;
+        let channels = self.channels as u16;
+        let block_align = self.frame_bytes() as u16;
+
+        // past two channels or 16 bits, the extensible format says which
+        // speaker each channel is for
+        let extensible = channels > 2 || bits > 16;
+        let mut fmt = Vec::with_capacity(40);
+        let outer_tag = if extensible {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            tag
+        };
+        fmt.extend_from_slice(&outer_tag.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&self.sample_rate.to_le_bytes());
+        fmt.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        if extensible {
+            fmt.extend_from_slice(&22u16.to_le_bytes());
+            fmt.extend_from_slice(&bits.to_le_bytes());
+            fmt.extend_from_slice(&channel_mask(self.channels).to_le_bytes());
+            fmt.extend_from_slice(&tag.to_le_bytes());
+            fmt.extend_from_slice(&SUBTYPE_GUID_TAIL);
+        }
+
+        let data_len = u32::try_from(data_len).unwrap_or(u32::MAX);
+        let riff_len = (4 + 8 + fmt.len() as u32 + 8).saturating_add(data_len);
+        let mut h = Vec::with_capacity(12 + 8 + fmt.len() + 8);
+        h.extend_from_slice(b"RIFF");
+        h.extend_from_slice(&riff_len.to_le_bytes());
+        h.extend_from_slice(b"WAVEfmt ");
+        h.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        h.extend_from_slice(&fmt);
+        h.extend_from_slice(b"data");
+        h.extend_from_slice(&data_len.to_le_bytes());
+        h
+    }
+
+    /// Write the header, with the sizes known so far, over the start of the
+    /// file, and flush everything.
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let data_len = self.data_len.unwrap_or(0);
+        let header = self.header(data_len);
+        self.f.seek(SeekFrom::Start(0))?;
+        self.f.write_all(&header)?;
+        self.f
+            .seek(SeekFrom::Start(header.len() as u64 + data_len))?;
+        self.data_len = Some(data_len);
+        self.f.flush()?;
+        if let Some(t) = &mut self.timestamps {
+            t.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// The speakers of a `channels` channel stream in WAV's channel mask, for the
+/// layouts the client plays (see [`crate::channels::ChannelMap`]); 0 for
+/// others.
+fn channel_mask(channels: usize) -> u32 {
+    const FL: u32 = 0x1;
+    const FR: u32 = 0x2;
+    const FC: u32 = 0x4;
+    const LFE: u32 = 0x8;
+    const BL: u32 = 0x10;
+    const BR: u32 = 0x20;
+    const BC: u32 = 0x100;
+    const SL: u32 = 0x200;
+    const SR: u32 = 0x400;
+    match channels {
+        1 => FC,
+        2 => FL | FR,
+        3 => FL | FR | FC,
+        4 => FL | FR | BL | BR,
+        5 => FL | FR | FC | BL | BR,
+        6 => FL | FR | FC | LFE | BL | BR,
+        7 => FL | FR | FC | LFE | BC | SL | SR,
+        8 => FL | FR | FC | LFE | BL | BR | SL | SR,
+        _ => 0,
+    }
+}
+
+/// The `n`th recording of a series at `path`: `path` itself first, then
+/// `out-1.wav`, `out-2.wav` and so on for `out.wav`.
+pub fn numbered(path: &Path, n: usize) -> PathBuf {
+    if n == 0 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}-{n}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{n}"),
+    };
+    path.with_file_name(name)
+}
+
+impl Player for Wav {
+    fn play(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+    fn write(&mut self, buf: &[i32]) -> anyhow::Result<()> {
+        let data_len = match self.data_len {
+            Some(len) => len,
+            None => {
+                // sizes are patched in by finish
+                let header = self.header(0);
+                self.f.write_all(&header)?;
+                0
+            }
+        };
+        let bytes = self.enc.encode(buf);
+        self.f.write_all(bytes)?;
+        self.data_len = Some(data_len + bytes.len() as u64);
+        Ok(())
+    }
+    fn latency_ms(&self) -> anyhow::Result<u16> {
+        Ok(0)
+    }
+    fn set_volume(&mut self, _val: u8) -> anyhow::Result<()> {
+        anyhow::bail!("the wav backend has no hardware mixer")
+    }
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    fn sample_format(&self) -> SampleFormat {
+        self.enc.format()
+    }
+    fn channels(&self) -> usize {
+        self.channels
+    }
+    fn mark_server_time(&mut self, server_ts: TimeVal) {
+        let frame = self.data_len.unwrap_or(0) / self.frame_bytes() as u64;
+        if let Some(t) = &mut self.timestamps {
+            if let Err(e) = writeln!(t, "{},{frame}", server_ts.to_micros()) {
+                log::warn!("writing a timestamp: {e}");
+            }
+        }
+    }
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.finish()
+    }
+}
+
+impl Drop for Wav {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::warn!("finishing the recording: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::PcmMetadata;
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("snapcast-wav-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn recordings_read_back() {
+        for (format, channels) in [
+            (SampleFormat::S16, 2),
+            (SampleFormat::S24, 2),
+            (SampleFormat::S24Packed, 2),
+            (SampleFormat::F32, 1),
+            (SampleFormat::S16, 6),
+        ] {
+            let path = temp("read-back.wav");
+            let samples: Vec<i32> = (0..channels as i32 * 10).map(|s| s << 20).collect();
+            let mut w = Wav::new(&path, 44_100, channels)
+                .unwrap()
+                .with_format(format);
+            w.write(&samples[..channels * 4]).unwrap();
+            w.write(&samples[channels * 4..]).unwrap();
+            w.close().unwrap();
+            drop(w);
+
+            let file = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let m = PcmMetadata::try_from(&file[..]).unwrap();
+            assert_eq!(m.channel_count as usize, channels);
+            assert_eq!(m.audio_rate, 44_100);
+            let data = file.windows(4).position(|w| w == b"data").unwrap() + 8;
+            let len = u32::from_le_bytes(file[data - 4..data].try_into().unwrap());
+            assert_eq!(len as usize, file.len() - data, "{format:?}");
+            let got: Vec<i32> = file[data..]
+                .chunks(m.format.bytes())
+                .map(|b| m.format.read(b))
+                .collect();
+            assert_eq!(got, samples, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn timestamps_map_chunks_to_frames() {
+        let (path, ts) = (temp("ts.wav"), temp("ts.csv"));
+        let mut w = Wav::new(&path, 48_000, 2)
+            .unwrap()
+            .with_timestamps(&ts)
+            .unwrap();
+        w.mark_server_time(TimeVal::from_micros(1_000_000));
+        w.write(&[0; 2 * 480]).unwrap();
+        w.mark_server_time(TimeVal::from_micros(1_010_000));
+        w.write(&[0; 2 * 480]).unwrap();
+        drop(w);
+        let log = std::fs::read_to_string(&ts).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&ts).unwrap();
+        assert_eq!(log, "server_time_us,frame\n1000000,0\n1010000,480\n");
+    }
+
+    #[test]
+    fn rotated_recordings_are_numbered() {
+        let p = Path::new("/rec/out.wav");
+        assert_eq!(numbered(p, 0), p);
+        assert_eq!(numbered(p, 2), Path::new("/rec/out-2.wav"));
+        assert_eq!(numbered(Path::new("take"), 1), Path::new("take-1"));
+    }
+}
@@ -1,6 +1,10 @@
 use core::ops::{Add, Div, Sub};
 use std::time::Duration;
 
+#[cfg(feature = "vorbis")]
+use crate::ogg::OggDemuxer;
+use crate::sample::SampleFormat;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Ord, PartialOrd)]
@@ -47,22 +51,19 @@ impl TimeVal {
         }
     }
     pub fn normalize(mut self) -> Self {
+        // sec wraps instead of overflowing: timestamps come off the wire
         while self.usec > 1_000_000 {
             self.usec -= 1_000_000;
-            self.sec += 1;
+            self.sec = self.sec.wrapping_add(1);
         }
         while self.usec < 0 {
             self.usec += 1_000_000;
-            self.sec -= 1;
+            self.sec = self.sec.wrapping_sub(1);
         }
         self
     }
     pub fn from_millis(millis: i32) -> TimeVal {
-        TimeVal {
-            sec: 0,
-            usec: millis * 1000,
-        }
-        .normalize()
+        TimeVal::from_micros(millis as i64 * 1000).normalize()
     }
     pub fn millis(&self) -> anyhow::Result<u16> {
         let s = self.normalize();
@@ -97,16 +98,16 @@ impl From<Duration> for TimeVal {
 impl Add<TimeVal> for TimeVal {
     type Output = TimeVal;
     fn add(self, other: TimeVal) -> TimeVal {
-        let sec = self.sec + other.sec;
-        let usec = self.usec + other.usec;
+        let sec = self.sec.wrapping_add(other.sec);
+        let usec = self.usec.wrapping_add(other.usec);
         TimeVal { sec, usec }.normalize()
     }
 }
 impl Sub<TimeVal> for TimeVal {
     type Output = TimeVal;
     fn sub(self, other: TimeVal) -> TimeVal {
-        let sec = self.sec - other.sec;
-        let usec = self.usec - other.usec;
+        let sec = self.sec.wrapping_sub(other.sec);
+        let usec = self.usec.wrapping_sub(other.usec);
 
         TimeVal { sec, usec }.normalize()
     }
@@ -138,6 +139,7 @@ pub enum MessageType {
 pub enum ClientMessage<'a> {
     Time(Time),
     Hello(ClientHello<'a>),
+    ClientInfo(ClientInfo),
 }
 
 #[derive(Debug, PartialEq)]
@@ -146,6 +148,7 @@ pub enum ServerMessage<'a> {
     CodecHeader(CodecHeader<'a>),
     WireChunk(WireChunk<'a>),
     Time(Time),
+    StreamTags(StreamTags),
 }
 
 impl TryFrom<u16> for MessageType {
@@ -186,21 +189,97 @@ fn slice_to_u32be(s: &[u8]) -> u32 {
 fn slice_to_u32(s: &[u8]) -> u32 {
     u32::from_le_bytes([s[0], s[1], s[2], s[3]])
 }
-impl<'a> From<&'a [u8]> for CodecHeader<'a> {
-    fn from(buf: &'a [u8]) -> CodecHeader<'a> {
-        let size = slice_to_u32(&buf[0..4]) as usize;
-        let codec_name_end = 4 + size;
-        let codec = std::str::from_utf8(&buf[4..codec_name_end]).unwrap();
 
-        let payload_len = slice_to_u32(&buf[codec_name_end..codec_name_end + 4]);
-        let payload = &buf[codec_name_end + 4..codec_name_end + 4 + payload_len as usize];
+/// Why a message payload could not be parsed. Payloads come straight off the
+/// socket, so every length and offset in them is checked instead of trusted.
+#[derive(Debug)]
+pub enum ProtoError {
+    /// The payload ended before the named field did.
+    Truncated(&'static str),
+    Utf8(std::str::Utf8Error),
+    Json(serde_json::Error),
+    /// A codec header for a codec this client cannot decode.
+    UnsupportedCodec(String),
+    /// Well-formed codec metadata describing a stream we cannot play.
+    UnsupportedFormat(String),
+    /// Structurally invalid data, such as a missing magic.
+    Malformed(&'static str),
+}
+
+impl std::fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtoError::Truncated(what) => write!(f, "truncated {what}"),
+            ProtoError::Utf8(e) => write!(f, "invalid UTF-8: {e}"),
+            ProtoError::Json(e) => write!(f, "invalid JSON: {e}"),
+            ProtoError::UnsupportedCodec(c) => write!(f, "unsupported codec {c:?}"),
+            ProtoError::UnsupportedFormat(what) => write!(f, "unsupported format: {what}"),
+            ProtoError::Malformed(what) => write!(f, "malformed {what}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProtoError::Utf8(e) => Some(e),
+            ProtoError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::str::Utf8Error> for ProtoError {
+    fn from(e: std::str::Utf8Error) -> ProtoError {
+        ProtoError::Utf8(e)
+    }
+}
+
+impl From<serde_json::Error> for ProtoError {
+    fn from(e: serde_json::Error) -> ProtoError {
+        ProtoError::Json(e)
+    }
+}
+
+/// `buf[start..start + len]`, or [`ProtoError::Truncated`] if that runs past the end.
+fn field<'a>(
+    buf: &'a [u8],
+    start: usize,
+    len: usize,
+    what: &'static str,
+) -> Result<&'a [u8], ProtoError> {
+    start
+        .checked_add(len)
+        .and_then(|end| buf.get(start..end))
+        .ok_or(ProtoError::Truncated(what))
+}
+
+/// The u32-length-prefixed field at `start`, and the offset just past it.
+fn prefixed<'a>(
+    buf: &'a [u8],
+    start: usize,
+    what: &'static str,
+) -> Result<(&'a [u8], usize), ProtoError> {
+    let len = slice_to_u32(field(buf, start, 4, what)?) as usize;
+    let body = field(buf, start + 4, len, what)?;
+    Ok((body, start + 4 + len))
+}
+
+impl<'a> TryFrom<&'a [u8]> for CodecHeader<'a> {
+    type Error = ProtoError;
+    fn try_from(buf: &'a [u8]) -> Result<CodecHeader<'a>, ProtoError> {
+        let (codec, end) = prefixed(buf, 0, "codec name")?;
+        let codec = std::str::from_utf8(codec)?;
+        let (payload, _) = prefixed(buf, end, "codec header")?;
         let metadata = match codec {
-            "opus" => CodecMetadata::Opus(OpusMetadata::from(payload)),
-            "flac" => CodecMetadata::Flac(FlacMetadata::from(payload)),
-            "pcm" => CodecMetadata::Pcm(PcmMetadata::from(payload)),
-            _ => todo!("unsupported codec {}", codec),
+            "opus" => CodecMetadata::Opus(OpusMetadata::try_from(payload)?),
+            "flac" => CodecMetadata::Flac(FlacMetadata::try_from(payload)?),
+            "pcm" => CodecMetadata::Pcm(PcmMetadata::try_from(payload)?),
+            #[cfg(feature = "vorbis")]
+            "ogg" => CodecMetadata::Vorbis(VorbisMetadata::try_from(payload)?),
+            other => return Err(ProtoError::UnsupportedCodec(other.to_string())),
         };
-        CodecHeader { codec, metadata }
+        Ok(CodecHeader { codec, metadata })
     }
 }
 impl CodecHeader<'_> {
@@ -240,11 +319,80 @@ impl ServerSettings {
         .as_buf(&payload_len_buf)
     }
 }
-impl<'a> From<&'a [u8]> for ServerSettings {
-    fn from(buf: &'a [u8]) -> ServerSettings {
-        let len = slice_to_u32(&buf[0..4]);
-        let s = std::str::from_utf8(&buf[4..4 + len as usize]).expect("Bad UTF8 data");
-        serde_json::from_str(s).unwrap()
+impl TryFrom<&[u8]> for ServerSettings {
+    type Error = ProtoError;
+    fn try_from(buf: &[u8]) -> Result<ServerSettings, ProtoError> {
+        let (json, _) = prefixed(buf, 0, "server settings")?;
+        Ok(serde_json::from_str(std::str::from_utf8(json)?)?)
+    }
+}
+
+impl StreamTags {
+    pub fn as_buf(&self, id: u16, now: TimeVal) -> Vec<u8> {
+        let mut tags = serde_json::Map::new();
+        let mut put = |key: &str, v: serde_json::Value| {
+            tags.insert(key.to_string(), v);
+        };
+        if let Some(t) = &self.title {
+            put("title", t.as_str().into());
+        }
+        if let Some(a) = &self.artist {
+            put("artist", vec![a.as_str()].into());
+        }
+        if let Some(a) = &self.album {
+            put("album", a.as_str().into());
+        }
+        if let Some(u) = &self.art_url {
+            put("artUrl", u.as_str().into());
+        }
+        let payload = serde_json::Value::Object(tags).to_string().into_bytes();
+        let mut payload_len_buf = u32::to_le_bytes(payload.len() as u32).to_vec();
+        payload_len_buf.extend_from_slice(&payload);
+        Base {
+            mtype: MessageType::StreamTags,
+            id,
+            refers_to: 0,
+            sent_tv: now,
+            received_tv: now,
+            size: payload_len_buf.len() as u32,
+        }
+        .as_buf(&payload_len_buf)
+    }
+}
+impl TryFrom<&[u8]> for StreamTags {
+    type Error = ProtoError;
+    /// Servers disagree on key spelling (`artist`, `ARTIST`, `xesam:artist`,
+    /// `mpris:artUrl`) and
+    /// on whether `artist` is a string or a list; accept all of them, and ignore
+    /// tags we do not surface.
+    fn try_from(buf: &[u8]) -> Result<StreamTags, ProtoError> {
+        let (json, _) = prefixed(buf, 0, "stream tags")?;
+        let json: serde_json::Value = serde_json::from_slice(json)?;
+        let obj = json
+            .as_object()
+            .ok_or(ProtoError::Malformed("stream tags: not a JSON object"))?;
+
+        let mut tags = StreamTags::default();
+        for (key, value) in obj {
+            // drop the MPRIS namespace, if any
+            let key = key.rsplit(':').next().unwrap_or(key).to_ascii_lowercase();
+            let slot = match key.as_str() {
+                "title" => &mut tags.title,
+                "artist" => &mut tags.artist,
+                "album" => &mut tags.album,
+                "arturl" => &mut tags.art_url,
+                _ => continue,
+            };
+            *slot = match value {
+                serde_json::Value::String(s) => Some(s.clone()),
+                serde_json::Value::Array(a) => {
+                    let parts: Vec<&str> = a.iter().filter_map(|v| v.as_str()).collect();
+                    (!parts.is_empty()).then(|| parts.join(", "))
+                }
+                _ => None,
+            };
+        }
+        Ok(tags)
     }
 }
 
@@ -264,21 +412,19 @@ impl WireChunk<'_> {
         .as_buf(&payload)
     }
 }
-impl<'a> From<&'a [u8]> for WireChunk<'a> {
-    fn from(buf: &'a [u8]) -> WireChunk<'a> {
-        let size = slice_to_u32(&buf[8..12]);
-        WireChunk {
-            timestamp: TimeVal::from(&buf[0..8]),
-            payload: &buf[12..12 + size as usize],
-        }
+impl<'a> TryFrom<&'a [u8]> for WireChunk<'a> {
+    type Error = ProtoError;
+    fn try_from(buf: &'a [u8]) -> Result<WireChunk<'a>, ProtoError> {
+        let timestamp = TimeVal::from(field(buf, 0, 8, "wire chunk timestamp")?);
+        let (payload, _) = prefixed(buf, 8, "wire chunk")?;
+        Ok(WireChunk { timestamp, payload })
     }
 }
 impl TryFrom<&[u8]> for Time {
-    type Error = anyhow::Error;
-    fn try_from(buf: &[u8]) -> anyhow::Result<Time> {
-        anyhow::ensure!(buf.len() >= 8, "short Time payload");
+    type Error = ProtoError;
+    fn try_from(buf: &[u8]) -> Result<Time, ProtoError> {
         Ok(Time {
-            latency: TimeVal::from(&buf[0..8]),
+            latency: TimeVal::from(field(buf, 0, 8, "time latency")?),
         })
     }
 }
@@ -314,12 +460,13 @@ impl Base {
 
     pub fn decode<'a>(&self, payload: &'a [u8]) -> anyhow::Result<ServerMessage<'a>> {
         Ok(match self.mtype {
-            MessageType::CodecHeader => ServerMessage::CodecHeader(CodecHeader::from(payload)),
+            MessageType::CodecHeader => ServerMessage::CodecHeader(CodecHeader::try_from(payload)?),
             MessageType::ServerSettings => {
-                ServerMessage::ServerSettings(ServerSettings::from(payload))
+                ServerMessage::ServerSettings(ServerSettings::try_from(payload)?)
             }
-            MessageType::WireChunk => ServerMessage::WireChunk(WireChunk::from(payload)),
+            MessageType::WireChunk => ServerMessage::WireChunk(WireChunk::try_from(payload)?),
             MessageType::Time => ServerMessage::Time(Time::try_from(payload)?),
+            MessageType::StreamTags => ServerMessage::StreamTags(StreamTags::try_from(payload)?),
             other => anyhow::bail!("unexpected server message type {other:?}"),
         })
     }
@@ -327,6 +474,7 @@ impl Base {
         Ok(match self.mtype {
             MessageType::Hello => ClientMessage::Hello(ClientHello::try_from(payload)?),
             MessageType::Time => ClientMessage::Time(Time::try_from(payload)?),
+            MessageType::ClientInfo => ClientMessage::ClientInfo(ClientInfo::try_from(payload)?),
             other => anyhow::bail!("unexpected client message type {other:?}"),
         })
     }
@@ -355,6 +503,10 @@ impl Time {
     /// Wire size of a Time message: 26-byte Base header + 8-byte latency payload.
     pub const WIRE_SIZE: usize = Base::BASE_SIZE + 8;
 
+    pub fn latency(&self) -> TimeVal {
+        self.latency
+    }
+
     pub fn as_buf(
         id: u16,
         refers_to: u16,
@@ -408,6 +560,58 @@ impl<'a> ClientHello<'a> {
     }
 }
 
+/// A client's local volume/mute, reported to the server (e.g. after a hardware
+/// knob was turned) so it can update its state for every other controller.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ClientInfo {
+    pub volume: u8,
+    pub muted: bool,
+}
+
+impl ClientInfo {
+    /// Longest JSON body: `{"volume":255,"muted":false}` is 28 bytes.
+    const MAX_JSON: usize = 32;
+    /// Upper bound on the wire size of a ClientInfo message.
+    pub const MAX_WIRE_SIZE: usize = Base::BASE_SIZE + 4 + ClientInfo::MAX_JSON;
+
+    pub fn as_buf(&self, id: u16, now: TimeVal) -> Vec<u8> {
+        let mut buf = vec![0; ClientInfo::MAX_WIRE_SIZE];
+        let n = self.write(&mut buf, id, now);
+        buf.truncate(n);
+        buf
+    }
+
+    /// Serialize into `out` without allocating, returning the bytes written.
+    /// `out` must hold at least [`ClientInfo::MAX_WIRE_SIZE`] bytes.
+    pub fn write(&self, out: &mut [u8], id: u16, now: TimeVal) -> usize {
+        let mut payload = [0u8; 4 + ClientInfo::MAX_JSON];
+        let json_len = {
+            let mut w = &mut payload[4..];
+            serde_json::to_writer(&mut w, self).expect("ClientInfo JSON fits in MAX_JSON");
+            ClientInfo::MAX_JSON - w.len()
+        };
+        payload[0..4].copy_from_slice(&u32::to_le_bytes(json_len as u32));
+        let payload = &payload[..4 + json_len];
+        Base {
+            mtype: MessageType::ClientInfo,
+            id,
+            refers_to: 0,
+            sent_tv: now,
+            received_tv: now,
+            size: payload.len() as u32,
+        }
+        .write(out, payload)
+    }
+}
+
+impl TryFrom<&[u8]> for ClientInfo {
+    type Error = ProtoError;
+    fn try_from(buf: &[u8]) -> Result<ClientInfo, ProtoError> {
+        let (json, _) = prefixed(buf, 0, "client info")?;
+        Ok(serde_json::from_slice(json)?)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[allow(non_snake_case)]
 pub struct ServerSettings {
@@ -417,6 +621,16 @@ pub struct ServerSettings {
     pub volume: u8,
 }
 
+/// Now-playing metadata pushed by servers with stream metadata enabled.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamTags {
+    pub title: Option<String>,
+    /// Multiple artists are joined with ", ".
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub art_url: Option<String>,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct OpusMetadata {
     pub sample_rate: u32,
@@ -439,40 +653,103 @@ impl OpusMetadata {
     }
 }
 
-impl From<&[u8]> for OpusMetadata {
-    fn from(buf: &[u8]) -> OpusMetadata {
+impl TryFrom<&[u8]> for OpusMetadata {
+    type Error = ProtoError;
+    fn try_from(buf: &[u8]) -> Result<OpusMetadata, ProtoError> {
+        let buf = field(buf, 0, 12, "opus header")?;
         let _marker = slice_to_u32(&buf[0..4]);
         let sample_rate = slice_to_u32(&buf[4..8]);
         let bit_depth = slice_to_u16(&buf[8..10]);
         let channel_count = slice_to_u16(&buf[10..12]);
-        OpusMetadata {
+        Ok(OpusMetadata {
             sample_rate,
             bit_depth,
             channel_count,
-        }
+        })
     }
 }
+/// WAVE format tags, from mmreg.h.
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct PcmMetadata {
     pub(crate) channel_count: u16,
     pub(crate) audio_rate: u32,
-    pub(crate) _bit_depth: u16,
-}
-
-impl From<&[u8]> for PcmMetadata {
-    fn from(buf: &[u8]) -> PcmMetadata {
-        assert_eq!(buf[0..4], [b'R', b'I', b'F', b'F']);
-        // +16 = remaining header len
-        let format_tag = slice_to_u16(&buf[20..22]);
-        assert_eq!(format_tag, 1); // PCM
-        let channel_count = slice_to_u16(&buf[22..24]);
-        let audio_rate = slice_to_u32(&buf[24..28]);
-        let bit_depth = slice_to_u16(&buf[34..36]);
-        PcmMetadata {
+    /// Significant bits per sample; fewer than the container for some formats.
+    pub(crate) bit_depth: u16,
+    /// How each sample is stored.
+    pub(crate) format: SampleFormat,
+}
+
+impl TryFrom<&[u8]> for PcmMetadata {
+    type Error = ProtoError;
+    fn try_from(buf: &[u8]) -> Result<PcmMetadata, ProtoError> {
+        let riff = field(buf, 0, 12, "RIFF header")?;
+        if riff[0..4] != *b"RIFF" || riff[8..12] != *b"WAVE" {
+            return Err(ProtoError::Malformed("pcm header: missing RIFF/WAVE magic"));
+        }
+        // chunks follow as (id, u32 length, data), padded to even lengths;
+        // the fmt chunk need not be the first one
+        let mut at = 12;
+        let fmt = loop {
+            let hdr = field(buf, at, 8, "WAVE fmt chunk")?;
+            let len = slice_to_u32(&hdr[4..8]) as usize;
+            if hdr[0..4] == *b"fmt " {
+                break field(buf, at + 8, len, "WAVE fmt chunk")?;
+            }
+            at = len
+                .checked_add(len % 2)
+                .and_then(|len| (at + 8).checked_add(len))
+                .ok_or(ProtoError::Truncated("WAVE fmt chunk"))?;
+        };
+        let fmt16 = field(fmt, 0, 16, "WAVE fmt chunk")?;
+        let mut format_tag = slice_to_u16(&fmt16[0..2]);
+        let channel_count = slice_to_u16(&fmt16[2..4]);
+        let audio_rate = slice_to_u32(&fmt16[4..8]);
+        let block_align = slice_to_u16(&fmt16[12..14]);
+        let container_bits = slice_to_u16(&fmt16[14..16]);
+        let mut bit_depth = container_bits;
+        if format_tag == WAVE_FORMAT_EXTENSIBLE {
+            let ext = field(fmt, 16, 24, "WAVE_FORMAT_EXTENSIBLE fields")?;
+            // valid bits, channel mask, then a GUID whose first two bytes are
+            // the actual format tag
+            let valid_bits = slice_to_u16(&ext[2..4]);
+            if valid_bits != 0 {
+                bit_depth = valid_bits;
+            }
+            format_tag = slice_to_u16(&ext[8..10]);
+        }
+        if channel_count == 0 {
+            return Err(ProtoError::Malformed("pcm header: no channels"));
+        }
+        // the container size comes from the block alignment where it is
+        // consistent: snapserver stores 24-bit samples in 4 bytes
+        let bytes = match block_align % channel_count {
+            0 if block_align > 0 => block_align / channel_count,
+            _ => container_bits.div_ceil(8),
+        };
+        let format = match (format_tag, bytes, container_bits) {
+            (WAVE_FORMAT_PCM, 1, _) => SampleFormat::U8,
+            (WAVE_FORMAT_PCM, 2, _) => SampleFormat::S16,
+            (WAVE_FORMAT_PCM, 3, _) => SampleFormat::S24Packed,
+            (WAVE_FORMAT_PCM, 4, 24) => SampleFormat::S24,
+            (WAVE_FORMAT_PCM, 4, _) => SampleFormat::S32,
+            (WAVE_FORMAT_IEEE_FLOAT, 4, _) => SampleFormat::F32,
+            (WAVE_FORMAT_IEEE_FLOAT, 8, _) => SampleFormat::F64,
+            _ => {
+                return Err(ProtoError::UnsupportedFormat(format!(
+                    "WAVE format tag {format_tag}, {container_bits} bits in {bytes} bytes"
+                )))
+            }
+        };
+        Ok(PcmMetadata {
             channel_count,
-            _bit_depth: bit_depth,
             audio_rate,
-        }
+            bit_depth,
+            format,
+        })
     }
 }
 
@@ -483,20 +760,86 @@ pub struct FlacMetadata {
     pub channel_count: u16,
 }
 
-impl From<&[u8]> for FlacMetadata {
-    fn from(buf: &[u8]) -> FlacMetadata {
-        let buf = &buf[4..]; // fLaC header
+impl TryFrom<&[u8]> for FlacMetadata {
+    type Error = ProtoError;
+    fn try_from(buf: &[u8]) -> Result<FlacMetadata, ProtoError> {
+        if field(buf, 0, 4, "flac header")? != b"fLaC" {
+            return Err(ProtoError::Malformed("flac header: missing fLaC marker"));
+        }
+        let buf = &buf[4..];
 
         // https://xiph.org/flac/format.html#def_STREAMINFO
-        let bitfield = slice_to_u32be(&buf[14..18]);
-        let sample_rate = (bitfield & 0xffff000) >> 12;
-        let channel_count = (bitfield & 0x0000_3_00) >> 8;
-        let bit_depth = bitfield & 0b11111;
-        FlacMetadata {
+        // after the block header and the block/frame sizes: 20 bits of sample
+        // rate, 3 of channels - 1, 5 of bits per sample - 1
+        let bitfield = slice_to_u32be(field(buf, 14, 4, "flac STREAMINFO")?);
+        let sample_rate = bitfield >> 12;
+        let channel_count = ((bitfield >> 9) & 0b111) + 1;
+        let bit_depth = ((bitfield >> 4) & 0b11111) + 1;
+        Ok(FlacMetadata {
             sample_rate,
             bit_depth: bit_depth as u16,
             channel_count: channel_count as u16,
+        })
+    }
+}
+
+/// Vorbis in Ogg, snapserver's `ogg` codec. The codec header holds the pages
+/// of the identification, comment and setup headers; chunks the pages of the
+/// audio packets. Only parsed with the `vorbis` feature; without it, `ogg`
+/// streams are an unsupported codec.
+#[cfg(feature = "vorbis")]
+#[derive(Debug, PartialEq, Clone)]
+pub struct VorbisMetadata {
+    pub sample_rate: u32,
+    pub channel_count: u16,
+    /// The three header packets, which the decoder is set up from.
+    pub(crate) headers: [Vec<u8>; 3],
+    /// The pages they came in, to send the header on as it was received.
+    pub(crate) pages: Vec<u8>,
+}
+
+#[cfg(feature = "vorbis")]
+impl TryFrom<&[u8]> for VorbisMetadata {
+    type Error = ProtoError;
+    fn try_from(buf: &[u8]) -> Result<VorbisMetadata, ProtoError> {
+        let mut packets = Vec::with_capacity(3);
+        OggDemuxer::new().push(buf, |p| {
+            if packets.len() < 3 {
+                packets.push(p.to_vec());
+            }
+            Ok::<_, ProtoError>(())
+        })?;
+        let headers: [Vec<u8>; 3] = packets
+            .try_into()
+            .map_err(|_| ProtoError::Truncated("vorbis headers"))?;
+        // each starts with its packet type, then "vorbis"
+        for (packet, kind) in headers.iter().zip([1, 3, 5]) {
+            if packet.first() != Some(&kind) || packet.get(1..7) != Some(b"vorbis") {
+                return Err(ProtoError::Malformed("vorbis headers: unexpected packet"));
+            }
+        }
+        // https://xiph.org/vorbis/doc/Vorbis_I_spec.html#x1-630004.2.2
+        // version, channels, rate
+        let ident = field(&headers[0], 7, 9, "vorbis identification header")?;
+        if slice_to_u32(&ident[0..4]) != 0 {
+            return Err(ProtoError::UnsupportedFormat(format!(
+                "vorbis version {}",
+                slice_to_u32(&ident[0..4])
+            )));
+        }
+        let channel_count = ident[4] as u16;
+        let sample_rate = slice_to_u32(&ident[5..9]);
+        if channel_count == 0 || sample_rate == 0 {
+            return Err(ProtoError::Malformed(
+                "vorbis identification header: no channels or rate",
+            ));
         }
+        Ok(VorbisMetadata {
+            sample_rate,
+            channel_count,
+            headers,
+            pages: buf.to_vec(),
+        })
     }
 }
 
@@ -505,6 +848,8 @@ pub enum CodecMetadata {
     Flac(FlacMetadata),
     Pcm(PcmMetadata),
     Opus(OpusMetadata),
+    #[cfg(feature = "vorbis")]
+    Vorbis(VorbisMetadata),
 }
 
 impl CodecMetadata {
@@ -513,6 +858,8 @@ impl CodecMetadata {
             CodecMetadata::Opus(o) => o.channel_count as usize,
             CodecMetadata::Pcm(p) => p.channel_count as usize,
             CodecMetadata::Flac(f) => f.channel_count as usize,
+            #[cfg(feature = "vorbis")]
+            CodecMetadata::Vorbis(v) => v.channel_count as usize,
         }
     }
     pub fn rate(&self) -> usize {
@@ -520,12 +867,31 @@ impl CodecMetadata {
             CodecMetadata::Opus(o) => o.sample_rate as usize,
             CodecMetadata::Pcm(p) => p.audio_rate as usize,
             CodecMetadata::Flac(f) => f.sample_rate as usize,
+            #[cfg(feature = "vorbis")]
+            CodecMetadata::Vorbis(v) => v.sample_rate as usize,
+        }
+    }
+    /// The narrowest sample format that holds the decoded stream exactly.
+    pub fn sample_format(&self) -> SampleFormat {
+        match self {
+            // the decoder's output is 16-bit
+            CodecMetadata::Opus(_) => SampleFormat::S16,
+            CodecMetadata::Pcm(p) => p.format,
+            CodecMetadata::Flac(f) => match f.bit_depth {
+                0..=16 => SampleFormat::S16,
+                17..=24 => SampleFormat::S24Packed,
+                _ => SampleFormat::S32,
+            },
+            #[cfg(feature = "vorbis")]
+            CodecMetadata::Vorbis(_) => SampleFormat::F32,
         }
     }
     fn as_payload(&self) -> Vec<u8> {
         match self {
             CodecMetadata::Opus(o) => o.as_payload().to_vec(),
-            // the server only ever emits opus; pcm/flac headers are decode-only
+            #[cfg(feature = "vorbis")]
+            CodecMetadata::Vorbis(v) => v.pages.clone(),
+            // the server only ever emits opus; the other headers are decode-only
             CodecMetadata::Pcm(_) => todo!("encoding pcm codec header"),
             CodecMetadata::Flac(_) => todo!("encoding flac codec header"),
         }
@@ -560,15 +926,12 @@ pub struct ClientHello<'a> {
     pub SnapStreamProtocolVersion: u8, // this one shouldn't be pub
 }
 impl<'a> TryFrom<&'a [u8]> for ClientHello<'a> {
-    type Error = anyhow::Error;
+    type Error = ProtoError;
     /// Fallible parse for untrusted input: a truncated or non-JSON Hello is a
     /// protocol error rather than a panic.
-    fn try_from(buf: &'a [u8]) -> anyhow::Result<ClientHello<'a>> {
-        anyhow::ensure!(buf.len() >= 4, "short hello");
-        let len = slice_to_u32(buf) as usize;
-        let end = len.checked_add(4).filter(|e| *e <= buf.len());
-        let end = end.ok_or_else(|| anyhow::anyhow!("hello length {len} out of range"))?;
-        Ok(serde_json::from_slice(&buf[4..end])?)
+    fn try_from(buf: &'a [u8]) -> Result<ClientHello<'a>, ProtoError> {
+        let (json, _) = prefixed(buf, 0, "hello")?;
+        Ok(serde_json::from_slice(json)?)
     }
 }
 
@@ -576,6 +939,19 @@ impl<'a> TryFrom<&'a [u8]> for ClientHello<'a> {
 mod tests {
     use super::*;
 
+    // payloads captured from a real snapserver
+    const PCM_CODEC_HEADER: &[u8] = &[
+        3, 0, 0, 0, 112, 99, 109, 44, 0, 0, 0, 82, 73, 70, 70, 36, 0, 0, 0, 87, 65, 86, 69, 102,
+        109, 116, 32, 16, 0, 0, 0, 1, 0, 2, 0, 128, 187, 0, 0, 0, 238, 2, 0, 4, 0, 16, 0, 100, 97,
+        116, 97, 0, 0, 0, 0,
+    ];
+    const SERVER_SETTINGS: &[u8] = &[
+        55, 0, 0, 0, 123, 34, 98, 117, 102, 102, 101, 114, 77, 115, 34, 58, 53, 48, 48, 44, 34,
+        108, 97, 116, 101, 110, 99, 121, 34, 58, 48, 44, 34, 109, 117, 116, 101, 100, 34, 58, 102,
+        97, 108, 115, 101, 44, 34, 118, 111, 108, 117, 109, 101, 34, 58, 49, 48, 48, 125,
+    ];
+    const TIME: &[u8] = &[169, 74, 16, 0, 217, 44, 6, 0];
+
     #[test]
     fn test_sub_timeval() {
         let tv1 = TimeVal { sec: 0, usec: 10 };
@@ -607,16 +983,135 @@ mod tests {
             metadata: CodecMetadata::Pcm(PcmMetadata {
                 channel_count: 2,
                 audio_rate: 48000,
-                _bit_depth: 16,
+                bit_depth: 16,
+                format: SampleFormat::S16,
             }),
         };
-        let buf: Vec<u8> = vec![
-            3, 0, 0, 0, 112, 99, 109, 44, 0, 0, 0, 82, 73, 70, 70, 36, 0, 0, 0, 87, 65, 86, 69,
-            102, 109, 116, 32, 16, 0, 0, 0, 1, 0, 2, 0, 128, 187, 0, 0, 0, 238, 2, 0, 4, 0, 16, 0,
-            100, 97, 116, 97, 0, 0, 0, 0,
-        ];
+        assert_eq!(CodecHeader::try_from(PCM_CODEC_HEADER).unwrap(), expected);
+    }
+
+    /// A WAV header with the given fmt chunk, after an unrelated chunk.
+    fn wav_header(tag: u16, channels: u16, block_align: u16, bits: u16, ext: &[u8]) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&44_100u32.to_le_bytes());
+        fmt.extend_from_slice(&(44_100 * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        fmt.extend_from_slice(ext);
+        let mut h = b"RIFF\0\0\0\0WAVELIST\x03\0\0\0abc\0fmt ".to_vec();
+        h.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        h.extend_from_slice(&fmt);
+        h.extend_from_slice(b"data\0\0\0\0");
+        h
+    }
+
+    #[test]
+    fn test_pcm_header_variants() {
+        let parse = |h: Vec<u8>| {
+            let m = PcmMetadata::try_from(&h[..]).unwrap();
+            (m.channel_count, m.bit_depth, m.format)
+        };
+        // snapserver's sampleformat=48000:24:2 keeps 24 bits in 4 bytes
+        assert_eq!(
+            parse(wav_header(1, 2, 8, 24, &[])),
+            (2, 24, SampleFormat::S24)
+        );
+        assert_eq!(
+            parse(wav_header(1, 2, 6, 24, &[])),
+            (2, 24, SampleFormat::S24Packed)
+        );
+        assert_eq!(parse(wav_header(1, 1, 1, 8, &[])), (1, 8, SampleFormat::U8));
+        assert_eq!(
+            parse(wav_header(3, 6, 24, 32, &[])),
+            (6, 32, SampleFormat::F32)
+        );
+
+        // extensible: 24 valid bits left-justified in 32, integer subformat
+        let mut ext = vec![22, 0, 24, 0, 3, 0, 0, 0, 1, 0];
+        ext.extend_from_slice(&[0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xaa, 0, 0x38, 0x9b, 0x71]);
+        assert_eq!(
+            parse(wav_header(0xfffe, 2, 8, 32, &ext)),
+            (2, 24, SampleFormat::S32)
+        );
+
+        let h = wav_header(2, 2, 4, 16, &[]);
+        assert!(matches!(
+            PcmMetadata::try_from(&h[..]),
+            Err(ProtoError::UnsupportedFormat(_))
+        ));
+        let h = wav_header(1, 2, 4, 16, &[]);
+        assert!(matches!(
+            PcmMetadata::try_from(&h[..30]),
+            Err(ProtoError::Truncated(_))
+        ));
+        // an odd chunk length at the top of the range must not overflow
+        let h = b"RIFF\0\0\0\0WAVELIST\xff\xff\xff\xff".to_vec();
+        assert!(matches!(
+            PcmMetadata::try_from(&h[..]),
+            Err(ProtoError::Truncated(_))
+        ));
+    }
+
+    #[test]
+    fn test_flac_streaminfo() {
+        // 96kHz, stereo, 24 bit
+        let bitfield: u32 = (96_000 << 12) | (1 << 9) | (23 << 4);
+        let mut header = b"fLaC\x80\x00\x00\x22".to_vec();
+        header.extend_from_slice(&[0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+        header.extend_from_slice(&bitfield.to_be_bytes());
+        header.extend_from_slice(&[0; 20]);
+        let expected = FlacMetadata {
+            sample_rate: 96_000,
+            bit_depth: 24,
+            channel_count: 2,
+        };
+        assert_eq!(FlacMetadata::try_from(&header[..]).unwrap(), expected);
+        assert_eq!(
+            CodecMetadata::Flac(expected).sample_format(),
+            SampleFormat::S24Packed
+        );
+    }
+
+    #[cfg(feature = "vorbis")]
+    #[test]
+    fn test_vorbis_headers() {
+        use crate::ogg::page;
+        // 44.1kHz, stereo; bitrates, block sizes and framing bit left at 0
+        let mut ident = b"\x01vorbis\0\0\0\0\x02".to_vec();
+        ident.extend_from_slice(&44_100u32.to_le_bytes());
+        ident.extend_from_slice(&[0; 14]);
+        let comment = b"\x03vorbis\x04\0\0\0test\0\0\0\0\x01";
+        let setup = b"\x05vorbis\0";
+        // libogg puts the identification header on a page of its own
+        let mut pages = page(&[&ident], false, false);
+        pages.extend(page(&[comment, setup], false, false));
 
-        assert_eq!(CodecHeader::from(buf.as_slice()), expected);
+        let mut payload = u32::to_le_bytes(3).to_vec();
+        payload.extend_from_slice(b"ogg");
+        payload.extend_from_slice(&u32::to_le_bytes(pages.len() as u32));
+        payload.extend_from_slice(&pages);
+        let header = CodecHeader::try_from(payload.as_slice()).unwrap();
+        let CodecMetadata::Vorbis(v) = &header.metadata else {
+            panic!("not vorbis: {header:?}");
+        };
+        assert_eq!((v.sample_rate, v.channel_count), (44_100, 2));
+        assert_eq!(v.headers, [ident.clone(), comment.to_vec(), setup.to_vec()]);
+        assert_eq!(header.metadata.as_payload(), pages);
+
+        // the setup header is missing
+        let short = page(&[&ident, comment], false, false);
+        assert!(matches!(
+            VorbisMetadata::try_from(&short[..]),
+            Err(ProtoError::Truncated(_))
+        ));
+        // or comes first
+        let swapped = page(&[setup, comment, &ident], false, false);
+        assert!(matches!(
+            VorbisMetadata::try_from(&swapped[..]),
+            Err(ProtoError::Malformed(_))
+        ));
     }
 
     #[test]
@@ -627,16 +1122,11 @@ mod tests {
             muted: false,
             volume: 100,
         };
-        let buf = &[
-            55, 0, 0, 0, 123, 34, 98, 117, 102, 102, 101, 114, 77, 115, 34, 58, 53, 48, 48, 44, 34,
-            108, 97, 116, 101, 110, 99, 121, 34, 58, 48, 44, 34, 109, 117, 116, 101, 100, 34, 58,
-            102, 97, 108, 115, 101, 44, 34, 118, 111, 108, 117, 109, 101, 34, 58, 49, 48, 48, 125,
-        ];
-        assert_eq!(ServerSettings::from(buf.as_slice()), expected);
+        assert_eq!(ServerSettings::try_from(SERVER_SETTINGS).unwrap(), expected);
         let str_ = r#"{"x":7,"bufferMs":500,"latency":0,"muted":false,"volume":100}"#;
         let len_buf = u32::to_le_bytes(str_.len() as u32);
         let buf2 = [&len_buf, str_.as_bytes()].concat();
-        assert_eq!(ServerSettings::from(buf2.as_slice()), expected);
+        assert_eq!(ServerSettings::try_from(buf2.as_slice()).unwrap(), expected);
     }
 
     #[test]
@@ -647,9 +1137,7 @@ mod tests {
                 usec: 404697,
             },
         };
-        let buf = &[169, 74, 16, 0, 217, 44, 6, 0];
-
-        assert_eq!(Time::try_from(buf.as_slice()).unwrap(), expected);
+        assert_eq!(Time::try_from(TIME).unwrap(), expected);
     }
 
     #[test]
@@ -735,6 +1223,20 @@ mod tests {
         assert_eq!(Base::try_from(buf_wc.as_slice()).unwrap(), exp_wc);
     }
 
+    /// `payload` behind a fresh header, as it would arrive on the wire.
+    fn frame(mtype: MessageType, payload: &[u8]) -> Vec<u8> {
+        let now = TimeVal { sec: 0, usec: 0 };
+        Base {
+            mtype,
+            id: 0,
+            refers_to: 0,
+            sent_tv: now,
+            received_tv: now,
+            size: payload.len() as u32,
+        }
+        .as_buf(payload)
+    }
+
     fn split(buf: &[u8]) -> (Base, &[u8]) {
         (
             Base::try_from(&buf[0..Base::BASE_SIZE]).unwrap(),
@@ -792,6 +1294,43 @@ mod tests {
         );
     }
 
+    #[test]
+    fn roundtrip_client_info() {
+        let now = TimeVal { sec: 1, usec: 2 };
+        for info in [
+            ClientInfo {
+                volume: 0,
+                muted: true,
+            },
+            ClientInfo {
+                volume: 255,
+                muted: false,
+            },
+        ] {
+            let buf = info.as_buf(4, now);
+            assert!(buf.len() <= ClientInfo::MAX_WIRE_SIZE);
+            let (base, payload) = split(&buf);
+            assert_eq!(base.mtype, MessageType::ClientInfo);
+            assert_eq!(base.id, 4);
+            assert_eq!(
+                base.decode_c(payload).unwrap(),
+                ClientMessage::ClientInfo(info)
+            );
+        }
+
+        // extra keys from official clients are ignored
+        let str_ = r#"{"volume":42,"muted":false,"latency":0}"#;
+        let len_buf = u32::to_le_bytes(str_.len() as u32);
+        let buf = [&len_buf, str_.as_bytes()].concat();
+        assert_eq!(
+            ClientInfo::try_from(buf.as_slice()).unwrap(),
+            ClientInfo {
+                volume: 42,
+                muted: false
+            }
+        );
+    }
+
     #[test]
     fn roundtrip_server_settings() {
         let now = TimeVal {
@@ -829,6 +1368,49 @@ mod tests {
         assert_eq!(base.decode(payload).unwrap(), ServerMessage::CodecHeader(ch));
     }
 
+    #[test]
+    fn test_stream_tags() {
+        let str_ = r#"{"ARTIST":"Daft Punk","TITLE":"Da Funk","ALBUM":"Homework","STREAM":"x"}"#;
+        let len_buf = u32::to_le_bytes(str_.len() as u32);
+        let buf = [&len_buf, str_.as_bytes()].concat();
+        let expected = StreamTags {
+            title: Some("Da Funk".into()),
+            artist: Some("Daft Punk".into()),
+            album: Some("Homework".into()),
+            art_url: None,
+        };
+        assert_eq!(StreamTags::try_from(buf.as_slice()).unwrap(), expected);
+
+        // MPRIS-style keys, with a list of artists
+        let str_ = r#"{"xesam:artist":["A","B"],"xesam:title":"T","mpris:artUrl":"u"}"#;
+        let len_buf = u32::to_le_bytes(str_.len() as u32);
+        let buf = [&len_buf, str_.as_bytes()].concat();
+        let tags = StreamTags::try_from(buf.as_slice()).unwrap();
+        assert_eq!(tags.artist.as_deref(), Some("A, B"));
+        assert_eq!(tags.title.as_deref(), Some("T"));
+        assert_eq!(tags.art_url.as_deref(), Some("u"));
+
+        assert!(StreamTags::try_from([4, 0, 0, 0, b'[', b']'].as_slice()).is_err());
+    }
+
+    #[test]
+    fn roundtrip_stream_tags() {
+        let now = TimeVal { sec: 3, usec: 4 };
+        let tags = StreamTags {
+            title: Some("Around the World".into()),
+            artist: Some("Daft Punk".into()),
+            album: None,
+            art_url: Some("http://example.com/cover.jpg".into()),
+        };
+        let buf = tags.as_buf(2, now);
+        let (base, payload) = split(&buf);
+        assert_eq!(base.mtype, MessageType::StreamTags);
+        assert_eq!(
+            base.decode(payload).unwrap(),
+            ServerMessage::StreamTags(tags)
+        );
+    }
+
     #[test]
     fn roundtrip_wire_chunk() {
         let now = TimeVal { sec: 55, usec: 66 };
@@ -853,4 +1435,133 @@ mod tests {
             other => panic!("expected WireChunk, got {other:?}"),
         }
     }
+
+    #[test]
+    fn truncated_payloads_are_errors() {
+        let now = TimeVal { sec: 1, usec: 2 };
+        let settings = ServerSettings {
+            bufferMs: 1000,
+            latency: 0,
+            muted: false,
+            volume: 50,
+        };
+        let opus = CodecHeader {
+            codec: "opus",
+            metadata: CodecMetadata::Opus(OpusMetadata {
+                sample_rate: 48000,
+                bit_depth: 16,
+                channel_count: 2,
+            }),
+        };
+        let wc = WireChunk {
+            timestamp: now,
+            payload: &[1, 2, 3, 4],
+        };
+        for msg in [
+            settings.as_buf(1, now),
+            opus.as_buf(2, now),
+            wc.as_buf(3, now),
+            StreamTags::default().as_buf(4, now),
+            Time::as_buf(5, 0, now, now, now),
+            frame(MessageType::CodecHeader, PCM_CODEC_HEADER),
+        ] {
+            let (base, payload) = split(&msg);
+            assert!(base.decode(payload).is_ok());
+            for end in 0..payload.len() {
+                assert!(
+                    base.decode(&payload[..end]).is_err(),
+                    "{:?} truncated to {end} bytes parsed",
+                    base.mtype
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_codec_headers() {
+        let mut aac = u32::to_le_bytes(3).to_vec();
+        aac.extend_from_slice(b"aac");
+        aac.extend_from_slice(&u32::to_le_bytes(0));
+        assert!(matches!(
+            CodecHeader::try_from(aac.as_slice()),
+            Err(ProtoError::UnsupportedCodec(c)) if c == "aac"
+        ));
+
+        let mut riff = PCM_CODEC_HEADER.to_vec();
+        riff[11] = b'X';
+        assert!(matches!(
+            CodecHeader::try_from(riff.as_slice()),
+            Err(ProtoError::Malformed(_))
+        ));
+
+        // IEEE float samples
+        let mut float = PCM_CODEC_HEADER.to_vec();
+        float[31] = 3;
+        assert!(matches!(
+            CodecHeader::try_from(float.as_slice()),
+            Err(ProtoError::UnsupportedFormat(_))
+        ));
+    }
+
+    /// Seeds the `decode` fuzz target with the vectors above as whole wire
+    /// messages: `cargo test write_fuzz_corpus -- --ignored`.
+    #[test]
+    #[ignore]
+    fn write_fuzz_corpus() {
+        let now = TimeVal { sec: 7, usec: 8 };
+        let opus = CodecHeader {
+            codec: "opus",
+            metadata: CodecMetadata::Opus(OpusMetadata {
+                sample_rate: 48000,
+                bit_depth: 16,
+                channel_count: 2,
+            }),
+        };
+        let tags = StreamTags {
+            title: Some("Around the World".into()),
+            artist: Some("Daft Punk".into()),
+            album: None,
+            art_url: None,
+        };
+        let hello = ClientHello {
+            MAC: "11:22:33:44:55:66",
+            HostName: "framework",
+            Version: "0.17.1",
+            ClientName: "CoolClient",
+            OS: "linux",
+            Arch: "x86_64",
+            Instance: 1,
+            ID: "11:22:33:44:55:66",
+            SnapStreamProtocolVersion: 2,
+        };
+        let wc = WireChunk {
+            timestamp: now,
+            payload: &[0; 64],
+        };
+        let info = ClientInfo {
+            volume: 40,
+            muted: true,
+        };
+        let seeds = [
+            (
+                "pcm_codec_header",
+                frame(MessageType::CodecHeader, PCM_CODEC_HEADER),
+            ),
+            ("opus_codec_header", opus.as_buf(0, now)),
+            (
+                "server_settings",
+                frame(MessageType::ServerSettings, SERVER_SETTINGS),
+            ),
+            ("time", frame(MessageType::Time, TIME)),
+            ("wire_chunk", wc.as_buf(0, now)),
+            ("stream_tags", tags.as_buf(0, now)),
+            ("hello", hello.as_buf()),
+            ("client_info", info.as_buf(0, now)),
+        ];
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/decode");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, buf) in seeds {
+            std::fs::write(dir.join(name), buf).unwrap();
+        }
+    }
 }
@@ -0,0 +1,237 @@
+use crate::client::{Client, ConnectedClient, Message};
+use crate::mdns;
+use std::time::{Duration, Instant};
+
+/// Wait before the first retry; doubled after every failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest wait between attempts, so a server that comes back is found within
+/// half a minute.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long each mDNS lookup listens for an answer.
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Where [`ReconnectingClient`] (re)connects to.
+pub enum Server {
+    /// Always this address, e.g. one given on the command line.
+    Addr(String),
+    /// Look up this mDNS service before every attempt, so a server that came
+    /// back on another address or port is still found.
+    Discover(String),
+}
+
+impl Server {
+    fn resolve(&self) -> anyhow::Result<String> {
+        match self {
+            Server::Addr(a) => Ok(a.clone()),
+            Server::Discover(service) => mdns::discover(service, DISCOVERY_TIMEOUT)?
+                .map(|a| a.to_string())
+                .ok_or_else(|| anyhow::anyhow!("no {service} server found on the network")),
+        }
+    }
+}
+
+/// Exponential backoff between connection attempts.
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff::new()
+    }
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff {
+            next: INITIAL_BACKOFF,
+        }
+    }
+
+    /// The wait before the next attempt, doubling the one after it.
+    pub fn next_delay(&mut self) -> Duration {
+        let d = self.next;
+        self.next = (d * 2).min(MAX_BACKOFF);
+        d
+    }
+
+    pub fn reset(&mut self) {
+        self.next = INITIAL_BACKOFF;
+    }
+}
+
+/// Keeps a [`ConnectedClient`] alive across socket failures: a dropped or
+/// failed connection is replaced by a fresh one (with a fresh
+/// [`crate::client::ClientMachine`]) after a backoff. All connections share one
+/// time base, so audible times stay comparable across reconnects.
+pub struct ReconnectingClient {
+    client: Client,
+    server: Server,
+    time_base: Instant,
+    conn: Option<ConnectedClient>,
+    /// The last tick on `conn` failed; it is dropped on the next one.
+    failed: bool,
+    backoff: Backoff,
+    /// When the next connection attempt may start.
+    retry_at: Instant,
+    /// A local volume/mute change made while disconnected.
+    pending_info: Option<(u8, bool)>,
+    /// Why the last connection or attempt failed, until taken.
+    last_error: Option<anyhow::Error>,
+}
+
+impl ReconnectingClient {
+    /// Connects lazily: the first attempt happens on the first `tick`.
+    pub fn new(client: Client, server: Server) -> ReconnectingClient {
+        let now = Instant::now();
+        ReconnectingClient {
+            client,
+            server,
+            time_base: now,
+            conn: None,
+            failed: false,
+            backoff: Backoff::new(),
+            retry_at: now,
+            pending_info: None,
+            last_error: None,
+        }
+    }
+
+    pub fn connected(&self) -> bool {
+        self.conn.is_some() && !self.failed
+    }
+
+    /// False while disconnected and until the new connection's clock settles.
+    pub fn synchronized(&self) -> bool {
+        self.connected() && self.conn.as_ref().is_some_and(|c| c.synchronized())
+    }
+
+    pub fn time_base(&self) -> Instant {
+        self.time_base
+    }
+
+    /// Why the last connection dropped or the last attempt failed, once; for
+    /// callers that report errors themselves rather than through the log.
+    pub fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.last_error.take()
+    }
+
+    /// Report a local volume/mute change to the server, on the next connection
+    /// if there is none right now.
+    pub fn send_client_info(&mut self, volume: u8, muted: bool) {
+        match self.conn {
+            Some(ref mut c) if !self.failed => c.send_client_info(volume, muted),
+            _ => self.pending_info = Some((volume, muted)),
+        }
+    }
+
+    /// Like [`ConnectedClient::tick`], but a socket failure drops the
+    /// connection instead of returning an error. While disconnected it blocks
+    /// until the next attempt is due, makes it, and returns
+    /// [`Message::Nothing`].
+    pub fn tick(&mut self) -> Message<'_> {
+        if self.failed {
+            self.conn = None;
+            self.failed = false;
+        }
+        if self.conn.is_none() {
+            self.reconnect();
+            return Message::Nothing;
+        }
+
+        let conn = self.conn.as_mut().unwrap();
+        match conn.tick() {
+            Ok(msg) => {
+                // only a connection that delivered something proves the server
+                // is back; one that is accepted then dropped keeps backing off
+                if !matches!(msg, Message::Nothing) {
+                    self.backoff.reset();
+                }
+                msg
+            }
+            Err(e) => {
+                log::warn!("connection lost: {e:#}");
+                self.last_error = Some(e);
+                self.failed = true;
+                self.retry_at = Instant::now() + self.backoff.next_delay();
+                Message::Nothing
+            }
+        }
+    }
+
+    fn reconnect(&mut self) {
+        let wait = self.retry_at.saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+        let attempt = self
+            .server
+            .resolve()
+            .and_then(|addr| self.client.connect_at(addr.as_str(), self.time_base));
+        match attempt {
+            Ok(mut c) => {
+                log::info!("connected");
+                if let Some((volume, muted)) = self.pending_info.take() {
+                    c.send_client_info(volume, muted);
+                }
+                self.conn = Some(c);
+            }
+            Err(e) => {
+                let delay = self.backoff.next_delay();
+                log::warn!("connecting failed, retrying in {delay:?}: {e:#}");
+                self.last_error = Some(e);
+                self.retry_at = Instant::now() + delay;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let mut b = Backoff::new();
+        let delays: Vec<_> = (0..9).map(|_| b.next_delay().as_millis()).collect();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 4000, 8000, 16000, 30000, 30000, 30000]
+        );
+        b.reset();
+        assert_eq!(b.next_delay(), INITIAL_BACKOFF);
+    }
+
+    #[test]
+    fn reconnects_after_the_server_hangs_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut s, _) = listener.accept().unwrap();
+                // take the Hello, then hang up
+                let mut buf = [0u8; 64];
+                _ = s.read(&mut buf);
+                tx.send(()).unwrap();
+            }
+        });
+
+        let client = Client::new("00:11:22:33:44:55".into(), "test".into());
+        let mut c = ReconnectingClient::new(client, Server::Addr(addr));
+        let mut accepted = 0;
+        for _ in 0..20 {
+            c.tick();
+            accepted += rx.try_iter().count();
+            if accepted == 2 {
+                break;
+            }
+        }
+        assert_eq!(accepted, 2);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::sample;
+
+/// Converts interleaved full-scale samples from the stream's rate to the
+/// player's, and applies the sync controller's rate correction on top
+/// (see [`crate::sync::SyncController::ppm`]). Between different rates, output
+/// frames are cubic (Catmull-Rom) interpolations of the input, which is good
+/// enough between the common rates; there is no anti-aliasing filter for large
+/// downsampling ratios. At the same rate, frames pass through untouched and
+/// the correction drops or repeats whole frames, in integer maths, so FPU-less
+/// targets pay nothing per sample.
+pub struct Resampler {
+    from: u32,
+    to: u32,
+    channels: usize,
+    /// Input frames per output frame, correction included.
+    step: f64,
+    /// The correction, rounded to whole ppm, for the same-rate path.
+    ppm: i64,
+    /// Millionths of a frame owed by the same-rate correction; a frame is
+    /// dropped or repeated each time this crosses +-1 frame.
+    debt: i64,
+    /// Position of the next output frame in `input`, in frames.
+    pos: f64,
+    /// The last frames of the previous call, which the next output frames
+    /// still interpolate from, followed by the new input.
+    input: Vec<i32>,
+    out: Vec<i32>,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, channels: usize) -> Resampler {
+        Resampler {
+            from,
+            to,
+            channels: channels.max(1),
+            step: from as f64 / to as f64,
+            ppm: 0,
+            debt: 0,
+            pos: 0.0,
+            input: Vec::new(),
+            out: Vec::new(),
+        }
+    }
+
+    /// Play `ppm` parts per million faster (or slower, when negative) than the
+    /// plain rate conversion.
+    pub fn set_ppm(&mut self, ppm: f64) {
+        self.step = self.from as f64 / self.to as f64 * (1.0 + ppm / 1e6);
+        self.ppm = ppm.round() as i64;
+    }
+
+    /// How far the next output frame lags the start of the next input, in
+    /// input frames: the frames held from earlier calls, less the part of
+    /// them already passed.
+    pub fn delay_frames(&self) -> f64 {
+        (self.input.len() / self.channels) as f64 - self.pos
+    }
+
+    /// Resample `samples`; the output is valid until the next call. Between
+    /// different rates up to two frames are held back, as the frames around
+    /// each output position must have arrived.
+    pub fn process<'a>(&'a mut self, samples: &'a [i32]) -> &'a [i32] {
+        let ch = self.channels;
+        if self.from == self.to {
+            let samples = &samples[..samples.len() - samples.len() % ch];
+            if self.ppm == 0 {
+                return samples;
+            }
+            return self.drop_or_repeat(samples);
+        }
+        self.input
+            .extend_from_slice(&samples[..samples.len() - samples.len() % ch]);
+        self.out.clear();
+        let frames = self.input.len() / ch;
+        loop {
+            let i = self.pos as usize;
+            if i + 2 >= frames {
+                break;
+            }
+            let t = self.pos - i as f64;
+            // at the very start there is no frame before the first one
+            let at = |f: usize, c: usize| self.input[f * ch + c] as f64;
+            for c in 0..ch {
+                let (x0, x1) = (at(i.max(1) - 1, c), at(i, c));
+                let (x2, x3) = (at(i + 1, c), at(i + 2, c));
+                let a = -0.5 * x0 + 1.5 * x1 - 1.5 * x2 + 0.5 * x3;
+                let b = x0 - 2.5 * x1 + 2.0 * x2 - 0.5 * x3;
+                let c1 = 0.5 * (x2 - x0);
+                let y = ((a * t + b) * t + c1) * t + x1;
+                self.out.push(sample::saturate(y));
+            }
+            self.pos += self.step;
+        }
+        // keep the frames the next output positions need, from the one before
+        let keep_from = (self.pos as usize).saturating_sub(1).min(frames);
+        self.input.drain(..keep_from * ch);
+        self.pos -= keep_from as f64;
+        &self.out
+    }
+
+    fn drop_or_repeat(&mut self, samples: &[i32]) -> &[i32] {
+        self.out.clear();
+        for frame in samples.chunks_exact(self.channels) {
+            self.debt += self.ppm;
+            if self.debt >= 1_000_000 {
+                self.debt -= 1_000_000;
+                continue;
+            }
+            self.out.extend_from_slice(frame);
+            if self.debt <= -1_000_000 {
+                self.debt += 1_000_000;
+                self.out.extend_from_slice(frame);
+            }
+        }
+        &self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_rate_passes_samples_through() {
+        let mut r = Resampler::new(48_000, 48_000, 2);
+        let samples: Vec<i32> = (0..100).flat_map(|n| [n << 16, -n << 16]).collect();
+        let mut out = r.process(&samples[..60]).to_vec();
+        out.extend_from_slice(r.process(&samples[60..]));
+        assert_eq!(out, samples);
+        assert_eq!(r.delay_frames(), 0.0);
+    }
+
+    #[test]
+    fn same_rate_correction_drops_and_repeats_whole_frames() {
+        let samples: Vec<i32> = (0..10_000).flat_map(|n| [n, -n]).collect();
+        // 300ppm over 10000 frames is 3 frames
+        let mut r = Resampler::new(48_000, 48_000, 2);
+        r.set_ppm(300.0);
+        let out = r.process(&samples).to_vec();
+        assert_eq!(out.len(), samples.len() - 6);
+        assert!(out
+            .chunks(2)
+            .zip(out.chunks(2).skip(1))
+            .all(|(a, b)| b[0] > a[0]));
+        r.set_ppm(-300.0);
+        let out = r.process(&samples);
+        assert_eq!(out.len(), samples.len() + 6);
+        assert!(out
+            .chunks(2)
+            .zip(out.chunks(2).skip(1))
+            .all(|(a, b)| b[0] >= a[0]));
+        assert_eq!(r.delay_frames(), 0.0);
+    }
+
+    #[test]
+    fn converts_between_rates() {
+        // a 1kHz sine at 48kHz comes out at 44.1kHz with its shape intact
+        let mut r = Resampler::new(48_000, 44_100, 1);
+        let sine = |rate: f64, n: usize| {
+            let x = (n as f64 * 1000.0 / rate * std::f64::consts::TAU).sin();
+            (x * (1 << 30) as f64) as i32
+        };
+        let mut out = Vec::new();
+        for chunk in (0..4800).collect::<Vec<_>>().chunks(480) {
+            let samples: Vec<i32> = chunk.iter().map(|&n| sine(48_000.0, n)).collect();
+            out.extend_from_slice(r.process(&samples));
+        }
+        assert!((4408..=4410).contains(&out.len()), "{}", out.len());
+        for (n, &s) in out.iter().enumerate() {
+            let error = (s - sine(44_100.0, n)).abs();
+            assert!(error < 1 << 20, "frame {n} off by {error}");
+        }
+    }
+
+    #[test]
+    fn rate_correction_changes_the_frame_count() {
+        let samples: Vec<i32> = (0..44_100).flat_map(|n| [n, -n]).collect();
+        // 1000ppm over a second is 48 frames
+        for (ppm, frames) in [(1000.0, 47_950), (-1000.0, 48_046)] {
+            let mut r = Resampler::new(44_100, 48_000, 2);
+            r.set_ppm(ppm);
+            let out = r.process(&samples);
+            assert!(
+                (out.len() / 2).abs_diff(frames) <= 1,
+                "{ppm}ppm: {} frames",
+                out.len() / 2
+            );
+            // channels stay paired
+            assert!(out.chunks(2).all(|f| f[0] == -f[1]));
+        }
+    }
+}
@@ -0,0 +1,199 @@
+/// How samples are laid out in bytes, little-endian, for a device or a PCM
+/// stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// Unsigned, centred on 128, as 8-bit WAV.
+    U8,
+    S16,
+    /// 24 bits in three bytes.
+    S24Packed,
+    /// 24 bits in the low three bytes of a 4-byte container, as ALSA's S24_LE
+    /// and snapserver's 24-bit PCM.
+    S24,
+    S32,
+    F32,
+    F64,
+}
+
+impl SampleFormat {
+    /// Bytes per sample.
+    pub fn bytes(&self) -> usize {
+        match self {
+            SampleFormat::U8 => 1,
+            SampleFormat::S16 => 2,
+            SampleFormat::S24Packed => 3,
+            SampleFormat::S24 | SampleFormat::S32 | SampleFormat::F32 => 4,
+            SampleFormat::F64 => 8,
+        }
+    }
+
+    /// Read one sample in this format from the start of `b` as a full-scale
+    /// `i32`.
+    pub fn read(&self, b: &[u8]) -> i32 {
+        match self {
+            SampleFormat::U8 => (b[0] as i32 - 128) << 24,
+            SampleFormat::S16 => (i16::from_le_bytes([b[0], b[1]]) as i32) << 16,
+            SampleFormat::S24Packed | SampleFormat::S24 => {
+                i32::from_le_bytes([0, b[0], b[1], b[2]])
+            }
+            SampleFormat::S32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            SampleFormat::F32 => from_float(f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64),
+            SampleFormat::F64 => from_float(f64::from_le_bytes(b[..8].try_into().unwrap())),
+        }
+    }
+}
+
+/// A full-scale sample from a float one, which is full scale at +-1.0.
+pub(crate) fn from_float(f: f64) -> i32 {
+    saturate(f * 2f64.powi(31))
+}
+
+/// `x` rounded to the nearest `i32`. Float samples can go past full scale
+/// (loud lossy audio, interpolation overshoot); Rust's float to int cast
+/// saturates, so those clip instead of wrapping around, and NaN is silence.
+pub(crate) fn saturate(x: f64) -> i32 {
+    x.round() as i32
+}
+
+/// Converts full-scale samples (the `i32`s that travel from the decoders
+/// through gain and sync, a 16-bit sample shifted up 16 bits, a 24-bit one
+/// 8) into a device's [`SampleFormat`]. Narrowing to 16 or 8 bits adds
+/// triangular dither, so the lost low bits become a little noise instead of
+/// distortion; samples that fit exactly pass untouched.
+pub struct SampleEncoder {
+    format: SampleFormat,
+    rng: u32,
+    buf: Vec<u8>,
+}
+
+impl SampleEncoder {
+    pub fn new(format: SampleFormat) -> SampleEncoder {
+        SampleEncoder {
+            format,
+            rng: 0x9e37_79b9,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    /// xorshift32; dither needs no better randomness than this
+    fn next_random(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    /// Round `s` to its top `bits`, dithered.
+    fn narrow(&mut self, s: i32, bits: u32) -> i32 {
+        let shift = 32 - bits;
+        let lsb = 1i64 << shift;
+        if s as i64 & (lsb - 1) == 0 {
+            return s >> shift;
+        }
+        // two uniform values sum to a triangle over +-1 LSB
+        let r1 = self.next_random() as i64 & (lsb - 1);
+        let r2 = self.next_random() as i64 & (lsb - 1);
+        let v = (s as i64 + r1 + r2 - (lsb - 1) + lsb / 2) >> shift;
+        let max = (1i64 << (bits - 1)) - 1;
+        v.clamp(-max - 1, max) as i32
+    }
+
+    /// Encode `samples`; the bytes are valid until the next call.
+    pub fn encode(&mut self, samples: &[i32]) -> &[u8] {
+        self.buf.clear();
+        self.buf.reserve(samples.len() * self.format.bytes());
+        for &s in samples {
+            match self.format {
+                SampleFormat::U8 => {
+                    let s = self.narrow(s, 8);
+                    self.buf.push((s + 128) as u8);
+                }
+                SampleFormat::S16 => {
+                    let s = self.narrow(s, 16);
+                    self.buf.extend_from_slice(&(s as i16).to_le_bytes());
+                }
+                SampleFormat::S24Packed => {
+                    self.buf.extend_from_slice(&(s >> 8).to_le_bytes()[..3]);
+                }
+                SampleFormat::S24 => self.buf.extend_from_slice(&(s >> 8).to_le_bytes()),
+                SampleFormat::S32 => self.buf.extend_from_slice(&s.to_le_bytes()),
+                SampleFormat::F32 => {
+                    let f = (s as f64 / 2f64.powi(31)) as f32;
+                    self.buf.extend_from_slice(&f.to_le_bytes());
+                }
+                SampleFormat::F64 => {
+                    let f = s as f64 / 2f64.powi(31);
+                    self.buf.extend_from_slice(&f.to_le_bytes());
+                }
+            }
+        }
+        &self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(format: SampleFormat, bytes: &[u8]) -> Vec<i32> {
+        bytes
+            .chunks(format.bytes())
+            .map(|b| format.read(b))
+            .collect()
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let samples = [0, i32::MIN, 0x7fff_ff00, -0x1234_5600, 0x100];
+        for format in [
+            SampleFormat::S24Packed,
+            SampleFormat::S24,
+            SampleFormat::S32,
+            SampleFormat::F32,
+            SampleFormat::F64,
+        ] {
+            let mut enc = SampleEncoder::new(format);
+            let got = decode(format, enc.encode(&samples));
+            assert_eq!(got, samples, "{format:?}");
+        }
+        // 16-bit content survives narrowing to S16 bit for bit
+        let samples = [0, i16::MIN as i32, i16::MAX as i32, -2].map(|s| s << 16);
+        let mut enc = SampleEncoder::new(SampleFormat::S16);
+        assert_eq!(decode(SampleFormat::S16, enc.encode(&samples)), samples);
+        let samples = [0, i8::MIN as i32, i8::MAX as i32].map(|s| s << 24);
+        let mut enc = SampleEncoder::new(SampleFormat::U8);
+        assert_eq!(enc.encode(&samples), [128, 0, 255]);
+        assert_eq!(decode(SampleFormat::U8, enc.encode(&samples)), samples);
+    }
+
+    #[test]
+    fn floats_past_full_scale_clip() {
+        assert_eq!(from_float(1.5), i32::MAX);
+        assert_eq!(from_float(-1.5), i32::MIN);
+        assert_eq!(from_float(f64::NAN), 0);
+        assert_eq!(from_float(-0.5), -(1 << 30));
+    }
+
+    #[test]
+    fn narrowing_to_16_bits_is_dithered() {
+        // a quarter of a 16-bit LSB above zero
+        let samples = vec![0x4000; 10_000];
+        let mut enc = SampleEncoder::new(SampleFormat::S16);
+        let out: Vec<i16> = enc
+            .encode(&samples)
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert!(out.iter().all(|s| (-1..=1).contains(s)));
+        // the dither averages out to the value that was rounded away
+        let mean = out.iter().map(|&s| s as f64).sum::<f64>() / out.len() as f64;
+        assert!((mean - 0.25).abs() < 0.05, "{mean}");
+        // and never wraps at full scale
+        let loud = enc.encode(&[i32::MAX, i32::MIN]).to_vec();
+        assert_eq!(decode(SampleFormat::S16, &loud), [0x7fff << 16, i32::MIN]);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::framing::Framing;
 pub use crate::framing::{Action, Event};
-use crate::proto::{ClientHello, ClientMessage, TimeVal};
+use crate::proto::{ClientHello, ClientInfo, ClientMessage, TimeVal};
 
 /// A semantic event decoded from a connected snapclient. The runtime turns these
 /// into wire replies; the session deliberately does not, because a Time reply's
@@ -15,6 +15,8 @@ pub enum SessionOutput<'a> {
         client_sent: TimeVal,
         received: TimeVal,
     },
+    /// The client's local volume/mute changed (e.g. a hardware knob).
+    ClientInfo(ClientInfo),
 }
 
 /// Socket-free server-side protocol core for one connected client. Mirrors
@@ -54,7 +56,7 @@ impl ServerSession {
                 Ok(SessionOutput::None)
             }
             Event::PacketReceived(bytes) => {
-                let base = self.framing.take_base();
+                let base = self.framing.take_base()?;
                 match base.decode_c(bytes)? {
                     ClientMessage::Hello(h) => {
                         self.greeted = true;
@@ -70,6 +72,12 @@ impl ServerSession {
                             received: now,
                         })
                     }
+                    ClientMessage::ClientInfo(info) => {
+                        if !self.greeted {
+                            anyhow::bail!("received a ClientInfo before Hello");
+                        }
+                        Ok(SessionOutput::ClientInfo(info))
+                    }
                 }
             }
         }
@@ -137,6 +145,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn client_info_after_hello() {
+        let mut s = ServerSession::new();
+        let info = ClientInfo {
+            volume: 17,
+            muted: true,
+        };
+        let buf = info.as_buf(1, TimeVal { sec: 0, usec: 0 });
+        let (hdr, payload) = buf.split_at(Base::BASE_SIZE);
+        s.handle_event(Event::HeaderReceived(hdr), TimeVal { sec: 0, usec: 0 })
+            .unwrap();
+        assert!(s
+            .handle_event(Event::PacketReceived(payload), TimeVal { sec: 0, usec: 0 })
+            .is_err());
+
+        let mut s = ServerSession::new();
+        feed(&mut s, &hello_bytes(), TimeVal { sec: 0, usec: 0 });
+        match feed(&mut s, &buf, TimeVal { sec: 0, usec: 0 }) {
+            SessionOutput::ClientInfo(got) => assert_eq!(got, info),
+            _ => panic!("expected ClientInfo"),
+        }
+    }
+
     #[test]
     fn time_before_hello_is_an_error() {
         let mut s = ServerSession::new();
@@ -0,0 +1,690 @@
+use crate::channels::{upmix, ChannelMap, ChannelMapper};
+use crate::client::{Client, Message};
+use crate::decoder::{Decode, Decoder};
+use crate::jitter::{Chunk, JitterBuffer, JitterStats, LatePolicy, Next};
+use crate::playback::{Player, Players};
+use crate::proto::{CodecHeader, CodecMetadata, ServerSettings, StreamTags, TimeVal};
+use crate::reconnect::{ReconnectingClient, Server};
+use crate::resample::Resampler;
+use crate::sync::{SyncAction, SyncController};
+use crate::volume::{SoftwareGain, VolumeCurve};
+use anyhow::Context;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Chunks held between the network and the player. At the usual 20ms chunks
+/// this is about 5s, well above any server buffer.
+const JITTER_CAPACITY: usize = 256;
+
+/// Builds the player for a new stream; called on every codec change.
+type PlayerFactory = Box<dyn FnMut(&CodecHeader) -> anyhow::Result<Players> + Send>;
+type Callback<T> = Arc<dyn Fn(&T) + Send + Sync>;
+type SyncCallback = Arc<dyn Fn(bool) + Send + Sync>;
+
+/// Where the server's volume and mute are applied.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VolumeControl {
+    /// Scale the samples before they reach the player; works with every backend.
+    #[default]
+    Software,
+    /// Drive the player's own volume control through [`Player::set_volume`].
+    Hardware,
+}
+
+/// A complete snapclient: connection (with reconnects), decoding, scheduling
+/// and playback. Built with [`SnapClient::builder`], driven by
+/// [`SnapClient::run`].
+pub struct SnapClient {
+    server: Server,
+    client: Client,
+    make_player: PlayerFactory,
+    volume_control: VolumeControl,
+    volume_curve: VolumeCurve,
+    channel_map: ChannelMap,
+    jitter_capacity: usize,
+    late_policy: LatePolicy,
+    on_settings: Option<Callback<ServerSettings>>,
+    on_stream_tags: Option<Callback<StreamTags>>,
+    on_sync: Option<SyncCallback>,
+    on_jitter: Option<Callback<JitterStats>>,
+    on_error: Option<Callback<anyhow::Error>>,
+}
+
+pub struct SnapClientBuilder {
+    server: Server,
+    client: Client,
+    make_player: Option<PlayerFactory>,
+    volume_control: VolumeControl,
+    volume_curve: VolumeCurve,
+    channel_map: ChannelMap,
+    jitter_capacity: usize,
+    late_policy: LatePolicy,
+    on_settings: Option<Callback<ServerSettings>>,
+    on_stream_tags: Option<Callback<StreamTags>>,
+    on_sync: Option<SyncCallback>,
+    on_jitter: Option<Callback<JitterStats>>,
+    on_error: Option<Callback<anyhow::Error>>,
+}
+
+impl SnapClient {
+    pub fn builder(server: Server) -> SnapClientBuilder {
+        SnapClientBuilder {
+            server,
+            client: Client::from_system(),
+            make_player: None,
+            volume_control: VolumeControl::default(),
+            volume_curve: VolumeCurve::default(),
+            channel_map: ChannelMap::default(),
+            jitter_capacity: JITTER_CAPACITY,
+            late_policy: LatePolicy::Drop,
+            on_settings: None,
+            on_stream_tags: None,
+            on_sync: None,
+            on_jitter: None,
+            on_error: None,
+        }
+    }
+}
+
+impl SnapClientBuilder {
+    /// Report this MAC instead of the one of the interface reaching the server.
+    pub fn with_mac(mut self, mac: &str) -> SnapClientBuilder {
+        self.client = self.client.with_mac(mac.to_string());
+        self
+    }
+
+    /// Report this hostname instead of the system's.
+    pub fn with_hostname(mut self, hostname: &str) -> SnapClientBuilder {
+        self.client = self.client.with_hostname(hostname.to_string());
+        self
+    }
+
+    /// The ID the server keeps this client's settings under; the MAC by default.
+    pub fn with_id(mut self, id: &str) -> SnapClientBuilder {
+        self.client = self.client.with_id(id.to_string());
+        self
+    }
+
+    /// Tells apart several clients on one host; 1 by default.
+    pub fn with_instance(mut self, instance: u8) -> SnapClientBuilder {
+        self.client = self.client.with_instance(instance);
+        self
+    }
+
+    pub fn with_client_name(mut self, name: &str) -> SnapClientBuilder {
+        self.client = self.client.with_client_name(name.to_string());
+        self
+    }
+
+    /// Build the player for each stream; it gets the stream's codec header to
+    /// pick its rate.
+    pub fn with_player(
+        mut self,
+        f: impl FnMut(&CodecHeader) -> anyhow::Result<Players> + Send + 'static,
+    ) -> SnapClientBuilder {
+        self.make_player = Some(Box::new(f));
+        self
+    }
+
+    pub fn with_volume_control(mut self, control: VolumeControl) -> SnapClientBuilder {
+        self.volume_control = control;
+        self
+    }
+
+    /// How the 0-100 volume maps onto software gain.
+    pub fn with_volume_curve(mut self, curve: VolumeCurve) -> SnapClientBuilder {
+        self.volume_curve = curve;
+        self
+    }
+
+    /// Which of the stream's channels are played, and how; all of them as
+    /// they are by default. The player factory should open the player with
+    /// [`ChannelMap::channels`] of the stream's channels.
+    pub fn with_channel_map(mut self, map: ChannelMap) -> SnapClientBuilder {
+        self.channel_map = map;
+        self
+    }
+
+    /// How many chunks may wait for the player; past that the oldest are
+    /// dropped.
+    pub fn with_jitter_capacity(mut self, chunks: usize) -> SnapClientBuilder {
+        self.jitter_capacity = chunks;
+        self
+    }
+
+    /// What to do with chunks that arrive after their audible time; dropped
+    /// by default.
+    pub fn with_late_policy(mut self, policy: LatePolicy) -> SnapClientBuilder {
+        self.late_policy = policy;
+        self
+    }
+
+    /// Called with every ServerSettings, after the volume was applied.
+    pub fn on_settings(
+        mut self,
+        f: impl Fn(&ServerSettings) + Send + Sync + 'static,
+    ) -> SnapClientBuilder {
+        self.on_settings = Some(Arc::new(f));
+        self
+    }
+
+    /// Called with the now-playing metadata whenever it changes.
+    pub fn on_stream_tags(
+        mut self,
+        f: impl Fn(&StreamTags) + Send + Sync + 'static,
+    ) -> SnapClientBuilder {
+        self.on_stream_tags = Some(Arc::new(f));
+        self
+    }
+
+    /// Called when the clock becomes synchronized (and playback starts), and
+    /// when it is lost again with the connection.
+    pub fn on_sync(mut self, f: impl Fn(bool) + Send + Sync + 'static) -> SnapClientBuilder {
+        self.on_sync = Some(Arc::new(f));
+        self
+    }
+
+    /// Called with the jitter buffer counters whenever a chunk was dropped or
+    /// missing, or the player ran dry. Without it those are logged.
+    pub fn on_jitter(
+        mut self,
+        f: impl Fn(&JitterStats) + Send + Sync + 'static,
+    ) -> SnapClientBuilder {
+        self.on_jitter = Some(Arc::new(f));
+        self
+    }
+
+    /// Called with errors the client recovers from: dropped connections and
+    /// failed reconnects, decode and playback failures.
+    pub fn on_error(
+        mut self,
+        f: impl Fn(&anyhow::Error) + Send + Sync + 'static,
+    ) -> SnapClientBuilder {
+        self.on_error = Some(Arc::new(f));
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<SnapClient> {
+        let Some(make_player) = self.make_player else {
+            anyhow::bail!("a SnapClient needs a player; see SnapClientBuilder::with_player");
+        };
+        Ok(SnapClient {
+            server: self.server,
+            client: self.client,
+            make_player,
+            volume_control: self.volume_control,
+            volume_curve: self.volume_curve,
+            channel_map: self.channel_map,
+            jitter_capacity: self.jitter_capacity,
+            late_policy: self.late_policy,
+            on_settings: self.on_settings,
+            on_stream_tags: self.on_stream_tags,
+            on_sync: self.on_sync,
+            on_jitter: self.on_jitter,
+            on_error: self.on_error,
+        })
+    }
+}
+
+/// The decoder of the current stream, how many channels it decodes to, and
+/// how those are mapped.
+struct StreamDecoder {
+    dec: Decoder,
+    channels: usize,
+    mapper: ChannelMapper,
+}
+
+impl StreamDecoder {
+    /// How much of a `len` sample buffer the decoder may fill, so its frames
+    /// still fit once spread over `out` channels in place.
+    fn decode_len(&self, len: usize, out: usize) -> usize {
+        len / self.channels.max(out) * self.channels
+    }
+
+    /// Map the first `n` decoded samples of `buf` and spread them over the
+    /// player's `out` channels; returns the new sample count.
+    fn for_player(&mut self, buf: &mut [i32], n: usize, out: usize) -> usize {
+        let n = self.mapper.map(buf, n);
+        upmix(buf, n, self.mapper.channels(), out)
+    }
+}
+
+/// State shared between the network loop and the playback thread. The gain
+/// and sync controller work on the player's channel layout at the stream's
+/// rate; the resampler converts that to the player's rate.
+struct Pipeline {
+    dec: Mutex<Option<StreamDecoder>>,
+    player: Mutex<Option<Players>>,
+    gain: Mutex<SoftwareGain>,
+    sync: Mutex<SyncController>,
+    resampler: Mutex<Resampler>,
+    jitter: Mutex<JitterBuffer>,
+    /// Signalled when a chunk is queued, or the pipeline closed.
+    queued: Condvar,
+    closed: AtomicBool,
+}
+
+/// Stops the playback thread and closes the player when the network loop
+/// returns.
+struct ClosePipeline<'a>(&'a Pipeline);
+
+impl Drop for ClosePipeline<'_> {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+        self.0.queued.notify_all();
+        if let Some(mut p) = self.0.player.lock().unwrap().take() {
+            if let Err(e) = p.close() {
+                log::warn!("closing the player: {e:#}");
+            }
+        }
+    }
+}
+
+impl SnapClient {
+    /// Run the client until the process exits. Connection failures are
+    /// retried with backoff, and a stream that cannot be set up (a codec
+    /// disabled at build time, a device that will not open) stays silent
+    /// until the next codec header; both are reported to
+    /// [`SnapClientBuilder::on_error`].
+    pub fn run(self) -> anyhow::Result<()> {
+        let SnapClient {
+            server,
+            client,
+            mut make_player,
+            volume_control,
+            volume_curve,
+            channel_map,
+            jitter_capacity,
+            late_policy,
+            on_settings,
+            on_stream_tags,
+            on_sync,
+            on_jitter,
+            on_error,
+        } = self;
+        let mut client = ReconnectingClient::new(client, server);
+
+        let pipeline = Arc::new(Pipeline {
+            dec: Mutex::new(None),
+            player: Mutex::new(None),
+            gain: Mutex::new(SoftwareGain::default()),
+            sync: Mutex::new(SyncController::new(48_000, 2)),
+            resampler: Mutex::new(Resampler::new(48_000, 48_000, 2)),
+            jitter: Mutex::new(JitterBuffer::new(jitter_capacity, late_policy)),
+            queued: Condvar::new(),
+            closed: AtomicBool::new(false),
+        });
+        pipeline.gain.lock().unwrap().set_curve(volume_curve);
+        let _close = ClosePipeline(&pipeline);
+
+        let time_base = client.time_base();
+        let p = pipeline.clone();
+        let on_error_2 = on_error.clone();
+        std::thread::spawn(move || play_samples(time_base, &p, on_jitter, on_error_2));
+
+        // the latest (volume, muted), re-applied to every new hardware player
+        let mut settings: Option<(u8, bool)> = None;
+        // every connection starts with a CodecHeader; when it matches the current
+        // stream the decoder and player carry on untouched
+        let mut stream: Option<CodecMetadata> = None;
+        let mut was_synced = false;
+        loop {
+            let in_sync = client.synchronized();
+            if in_sync != was_synced {
+                was_synced = in_sync;
+                if let Some(f) = &on_sync {
+                    f(in_sync);
+                }
+            }
+            match client.tick() {
+                Message::CodecHeader(ch) => {
+                    if stream.as_ref() == Some(&ch.metadata) {
+                        // the same stream again (after a reconnect, or another
+                        // source of the same format): what follows does not run
+                        // on from the last chunk
+                        if let Some(d) = pipeline.dec.lock().unwrap().as_mut() {
+                            if let Err(e) = d.dec.reset() {
+                                report(&on_error, e.context("resetting the decoder"));
+                            }
+                        }
+                        continue;
+                    }
+                    let hw_volume = match volume_control {
+                        VolumeControl::Hardware => settings,
+                        VolumeControl::Software => None,
+                    };
+                    let setup = StreamSetup {
+                        channel_map,
+                        hw_volume,
+                        on_error: &on_error,
+                    };
+                    stream = match setup.start(&pipeline, &ch, &mut make_player) {
+                        Ok(()) => Some(ch.metadata.clone()),
+                        Err(e) => {
+                            report(&on_error, e.context("setting up the stream"));
+                            None
+                        }
+                    };
+                }
+                // before the offset buffer fills, audible_at is computed from a
+                // bogus clock offset; forwarding those would schedule playback
+                // wildly in the future. Without a stream there is nothing to
+                // play them on.
+                Message::WireChunk(wc, audible_at) if in_sync && stream.is_some() => {
+                    pipeline
+                        .jitter
+                        .lock()
+                        .unwrap()
+                        .push(audible_at, wc.timestamp, wc.payload);
+                    pipeline.queued.notify_one();
+                }
+                Message::ServerSettings(s) => {
+                    settings = Some((s.volume, s.muted));
+                    match volume_control {
+                        VolumeControl::Software => {
+                            pipeline.gain.lock().unwrap().set(s.volume, s.muted)
+                        }
+                        VolumeControl::Hardware => {
+                            if let Some(p) = pipeline.player.lock().unwrap().as_mut() {
+                                set_hw_volume(p, s.volume, s.muted, &on_error);
+                            }
+                        }
+                    }
+                    if let Some(f) = &on_settings {
+                        f(&s);
+                    }
+                }
+                Message::StreamTags(t) => {
+                    if let Some(f) = &on_stream_tags {
+                        f(&t);
+                    }
+                }
+                _ => (),
+            }
+            if let Some(e) = client.take_error() {
+                report(&on_error, e);
+            }
+        }
+    }
+}
+
+/// What a new stream is set up with, besides its codec header.
+struct StreamSetup<'a> {
+    channel_map: ChannelMap,
+    /// The server's (volume, muted), for a player with hardware volume.
+    hw_volume: Option<(u8, bool)>,
+    on_error: &'a Option<Callback<anyhow::Error>>,
+}
+
+impl StreamSetup<'_> {
+    /// Replace the decoder and player with those of a new stream. Both stay
+    /// locked throughout, taken in the playback thread's order, so it never
+    /// pairs one stream's decoder with another's player. The old player is
+    /// closed before the new one is made, as most devices open only once; in
+    /// between, the stream is silent.
+    fn start(
+        &self,
+        pipeline: &Pipeline,
+        ch: &CodecHeader,
+        make_player: &mut PlayerFactory,
+    ) -> anyhow::Result<()> {
+        let mut dec = pipeline.dec.lock().unwrap();
+        let mut player = pipeline.player.lock().unwrap();
+        // chunks of the old stream would go to the new decoder
+        pipeline.jitter.lock().unwrap().clear();
+        *dec = None;
+        if let Some(mut old) = player.take() {
+            if let Err(e) = old.close() {
+                report(self.on_error, e.context("closing the player"));
+            }
+        }
+
+        let (rate, channels) = (ch.metadata.rate(), ch.metadata.channels());
+        let d = StreamDecoder {
+            dec: make_decoder(&ch.metadata)?,
+            channels,
+            mapper: ChannelMapper::new(self.channel_map, channels)?,
+        };
+        let mut p = make_player(ch)?;
+        anyhow::ensure!(
+            d.mapper.channels() <= p.channels(),
+            "cannot play {} channels on a {} channel player",
+            d.mapper.channels(),
+            p.channels()
+        );
+        pipeline.gain.lock().unwrap().set_channels(p.channels());
+        pipeline
+            .sync
+            .lock()
+            .unwrap()
+            .set_format(rate as u32, p.channels());
+        if p.sample_rate() != rate as u32 {
+            log::info!("resampling {rate}Hz to the player's {}Hz", p.sample_rate());
+        }
+        *pipeline.resampler.lock().unwrap() =
+            Resampler::new(rate as u32, p.sample_rate(), p.channels());
+        if let Some((volume, muted)) = self.hw_volume {
+            set_hw_volume(&mut p, volume, muted, self.on_error);
+        }
+        *dec = Some(d);
+        *player = Some(p);
+        Ok(())
+    }
+}
+
+fn set_hw_volume(
+    p: &mut Players,
+    volume: u8,
+    muted: bool,
+    on_error: &Option<Callback<anyhow::Error>>,
+) {
+    let volume = if muted { 0 } else { volume };
+    if let Err(e) = p.set_volume(volume) {
+        report(on_error, e.context("setting the player volume"));
+    }
+}
+
+fn report(on_error: &Option<Callback<anyhow::Error>>, e: anyhow::Error) {
+    match on_error {
+        Some(f) => f(&e),
+        None => log::error!("{e:#}"),
+    }
+}
+
+fn make_decoder(metadata: &CodecMetadata) -> anyhow::Result<Decoder> {
+    #[allow(unreachable_patterns)]
+    Ok(match metadata {
+        CodecMetadata::Pcm(cfg) => Decoder::new_pcm(cfg),
+        #[cfg(feature = "flac")]
+        CodecMetadata::Flac(cfg) => Decoder::new_flac(cfg)?,
+        #[cfg(feature = "opus")]
+        CodecMetadata::Opus(cfg) => Decoder::new_opus(cfg)?,
+        #[cfg(feature = "vorbis")]
+        CodecMetadata::Vorbis(cfg) => Decoder::new_vorbis(cfg)?,
+        other => anyhow::bail!("codec disabled at build time: {other:?}"),
+    })
+}
+
+/// Wait for the next thing to play, or `None` once the pipeline is closed.
+/// Reports the buffer counters when they show trouble.
+fn next_chunk(
+    time_base: Instant,
+    pipeline: &Pipeline,
+    last: &mut JitterStats,
+    on_jitter: &Option<Callback<JitterStats>>,
+) -> Option<Next> {
+    let mut jitter = pipeline.jitter.lock().unwrap();
+    loop {
+        if pipeline.closed.load(Ordering::Acquire) {
+            return None;
+        }
+        let next = jitter.pop(time_base.elapsed().into());
+        let stats = jitter.stats();
+        if (stats.expired, stats.overruns, stats.underruns, stats.gaps)
+            != (last.expired, last.overruns, last.underruns, last.gaps)
+        {
+            match on_jitter {
+                Some(f) => f(&stats),
+                None => log::warn!("jitter buffer: {stats:?}"),
+            }
+        }
+        *last = stats;
+        if next.is_some() {
+            return next;
+        }
+        // woken early by a push; the timeout notices underruns
+        jitter = pipeline
+            .queued
+            .wait_timeout(jitter, Duration::from_millis(20))
+            .unwrap()
+            .0;
+    }
+}
+
+fn play_samples(
+    time_base: Instant,
+    pipeline: &Pipeline,
+    on_jitter: Option<Callback<JitterStats>>,
+    on_error: Option<Callback<anyhow::Error>>,
+) {
+    // >= (5760 * 2) for OPUS (120ms frames)
+    // >= 2880 for PCM
+    // >= 4600 for FLAC
+    // for up to 8 channels, as streams are mapped and upmixed in place
+    let mut samples_out = vec![0; 5760 * 8 + 100];
+    let mut stats = JitterStats::default();
+
+    while let Some(next) = next_chunk(time_base, pipeline, &mut stats, &on_jitter) {
+        let chunk = match next {
+            Next::Chunk(chunk) => chunk,
+            Next::Gap(d) => {
+                if let Err(e) = play_gap(pipeline, d, &mut samples_out) {
+                    report(&on_error, e);
+                }
+                continue;
+            }
+        };
+        let played = play_chunk(pipeline, &chunk, time_base, &mut samples_out);
+        let mut jitter = pipeline.jitter.lock().unwrap();
+        match played {
+            Ok(Some(length)) => jitter.played_until(chunk.audible_at + length),
+            Ok(None) => (),
+            Err(e) => report(&on_error, e),
+        }
+        jitter.recycle(chunk.data);
+    }
+}
+
+/// Fill a hole in the stream so the player neither runs dry nor shifts the
+/// audio after it: concealed by the decoder where it can, silence otherwise.
+fn play_gap(pipeline: &Pipeline, d: Duration, buf: &mut [i32]) -> anyhow::Result<()> {
+    let Some(ref mut stream) = *pipeline.dec.lock().unwrap() else {
+        return Ok(());
+    };
+    let Some(ref mut p) = *pipeline.player.lock().unwrap() else {
+        return Ok(());
+    };
+    let (rate, channels) = {
+        let sync = pipeline.sync.lock().unwrap();
+        (sync.rate(), sync.channels())
+    };
+    let decode_len = stream.decode_len(buf.len(), channels);
+    let mut frames = (d.as_micros() as u64 * rate as u64 / 1_000_000) as usize;
+    while frames > 0 {
+        let mut n = stream
+            .dec
+            .conceal(frames, &mut buf[..decode_len])
+            .context("concealing")?;
+        n = match n {
+            0 => {
+                let n = (frames * channels).min(buf.len() / channels * channels);
+                buf[..n].fill(0);
+                n
+            }
+            n => stream.for_player(buf, n, channels),
+        };
+        pipeline.gain.lock().unwrap().apply(&mut buf[..n]);
+        let mut resampler = pipeline.resampler.lock().unwrap();
+        p.write(resampler.process(&buf[..n])).context("playing")?;
+        frames = frames.saturating_sub(n / channels);
+    }
+    Ok(())
+}
+
+/// Decode, schedule and write one chunk; returns how much of the timeline it
+/// covers, or `None` when there was no stream to play it on.
+fn play_chunk(
+    pipeline: &Pipeline,
+    chunk: &Chunk,
+    time_base: Instant,
+    samples_out: &mut [i32],
+) -> anyhow::Result<Option<TimeVal>> {
+    // a player that is not running yet (or just underran) has nothing queued
+    let delay_us = match *pipeline.player.lock().unwrap() {
+        Some(ref p) => p.delay_us().unwrap_or(0),
+        None => return Ok(None),
+    };
+    let now: TimeVal = time_base.elapsed().into();
+    let error_us = (now - chunk.audible_at).to_micros() + delay_us;
+    let action = pipeline.sync.lock().unwrap().update(error_us);
+    let skip_frames = match action {
+        SyncAction::Play => 0,
+        SyncAction::Wait(d) => {
+            std::thread::sleep(d);
+            0
+        }
+        SyncAction::Skip(frames) => frames,
+    };
+
+    // Guard against chunks coming before the decoder is initialized
+    let Some(ref mut stream) = *pipeline.dec.lock().unwrap() else {
+        return Ok(None);
+    };
+    let Some(ref mut p) = *pipeline.player.lock().unwrap() else {
+        return Ok(None);
+    };
+    let sync = pipeline.sync.lock().unwrap();
+    let decode_len = stream.decode_len(samples_out.len(), sync.channels());
+    let n = stream
+        .dec
+        .decode_sample(&chunk.data, &mut samples_out[..decode_len])
+        .map_err(|e| e.context("decoding"))?;
+    let mut n = stream.for_player(samples_out, n, sync.channels());
+    let length_us = (n / sync.channels()) as i64 * 1_000_000 / sync.rate() as i64;
+    let length = TimeVal::from_micros(length_us);
+    let skip = skip_frames.saturating_mul(sync.channels()).min(n);
+    if skip > 0 {
+        samples_out.copy_within(skip..n, 0);
+        n -= skip;
+    }
+    if n == 0 {
+        return Ok(Some(length));
+    }
+    pipeline.gain.lock().unwrap().apply(&mut samples_out[..n]);
+    let mut resampler = pipeline.resampler.lock().unwrap();
+    resampler.set_ppm(sync.ppm());
+    // where the next frame written falls on the server's timeline: past the
+    // skipped frames, behind those the resampler holds
+    let first_frame = (skip / sync.channels()) as f64 - resampler.delay_frames();
+    let offset_us = (first_frame * 1e6 / sync.rate() as f64).round() as i64;
+    p.mark_server_time(TimeVal::from_micros(
+        chunk.server_ts.to_micros() + offset_us,
+    ));
+    drop(sync);
+    p.play()
+        .and_then(|()| p.write(resampler.process(&samples_out[..n])))
+        .map_err(|e| e.context("playing"))?;
+    Ok(Some(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_needs_a_player() {
+        let b = SnapClient::builder(Server::Addr("127.0.0.1:1704".into()));
+        assert!(b.build().is_err());
+    }
+}
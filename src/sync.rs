@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+/// Errors beyond this are fixed in one step (waiting or skipping audio) rather
+/// than by nudging the rate, which at [`MAX_PPM`] would take tens of seconds.
+const HARD_SYNC_US: i64 = 10_000;
+
+/// Largest rate correction. 500ppm is a pitch change far below what anyone
+/// hears, and an order of magnitude above real crystal drift.
+const MAX_PPM: f64 = 500.0;
+
+/// Correction per microsecond of (filtered) error, in ppm: 1ms late plays
+/// 20ppm fast.
+const KP: f64 = 0.02;
+
+/// Integral gain per update, so the controller learns the DAC's steady drift
+/// and holds the error near zero instead of at a proportional offset.
+const KI: f64 = 0.000_2;
+
+/// Weight of a new measurement in the error average. Player delay readings
+/// move in whole periods, so single readings are too coarse to steer by.
+const ERROR_SMOOTHING: f64 = 0.05;
+
+/// What to do with the chunk being played, from [`SyncController::update`].
+#[derive(Debug, PartialEq)]
+pub enum SyncAction {
+    /// Close enough: play it at [`SyncController::ppm`].
+    Play,
+    /// Too early for rate correction: sleep this long first.
+    Wait(Duration),
+    /// Too late for rate correction: drop this many frames from its start.
+    Skip(usize),
+}
+
+/// Keeps playback aligned with the server clock over long sessions. Each chunk,
+/// the caller reports how far off the output is (when a sample written now
+/// will be heard, against when it should be); small errors are corrected
+/// continuously by nudging the playback rate through a
+/// [`crate::resample::Resampler`], large ones at once. `rate` is the stream's,
+/// which skipped frames are counted in.
+pub struct SyncController {
+    rate: u32,
+    channels: usize,
+    filtered_us: f64,
+    integral_ppm: f64,
+    ppm: f64,
+}
+
+impl SyncController {
+    pub fn new(rate: u32, channels: usize) -> SyncController {
+        SyncController {
+            rate,
+            channels: channels.max(1),
+            filtered_us: 0.0,
+            integral_ppm: 0.0,
+            ppm: 0.0,
+        }
+    }
+
+    /// Switch to a new stream format, forgetting everything learned: a new
+    /// stream usually means a new player, with its own clock.
+    pub fn set_format(&mut self, rate: u32, channels: usize) {
+        *self = SyncController::new(rate, channels);
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Current rate correction: positive plays faster to catch up, negative
+    /// slower to wait.
+    pub fn ppm(&self) -> f64 {
+        self.ppm
+    }
+
+    /// Feed the playout error for the next chunk: positive when audio written
+    /// now would be heard after its scheduled time.
+    pub fn update(&mut self, error_us: i64) -> SyncAction {
+        if error_us < -HARD_SYNC_US {
+            self.filtered_us = 0.0;
+            return SyncAction::Wait(Duration::from_micros(error_us.unsigned_abs()));
+        }
+        if error_us > HARD_SYNC_US {
+            self.filtered_us = 0.0;
+            let frames = error_us as u64 * self.rate as u64 / 1_000_000;
+            return SyncAction::Skip(frames as usize);
+        }
+
+        self.filtered_us += (error_us as f64 - self.filtered_us) * ERROR_SMOOTHING;
+        self.integral_ppm = (self.integral_ppm + self.filtered_us * KI).clamp(-MAX_PPM, MAX_PPM);
+        self.ppm = (self.filtered_us * KP + self.integral_ppm).clamp(-MAX_PPM, MAX_PPM);
+        SyncAction::Play
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resample::Resampler;
+
+    #[test]
+    fn large_errors_are_fixed_at_once() {
+        let mut s = SyncController::new(48_000, 2);
+        assert_eq!(
+            s.update(-500_000),
+            SyncAction::Wait(Duration::from_millis(500))
+        );
+        assert_eq!(s.update(20_000), SyncAction::Skip(960));
+        assert_eq!(s.update(1_000), SyncAction::Play);
+        assert!(s.ppm() > 0.0);
+    }
+
+    /// A DAC whose clock runs `drift_ppm` fast, fed 20ms chunks by a writer
+    /// that blocks while its queue holds `QUEUE` frames, like ALSA.
+    fn simulate(drift_ppm: f64, chunks: usize) -> (SyncController, f64) {
+        const CHUNK: usize = 960;
+        const QUEUE: f64 = 4800.0;
+        let rate = 48_000.0;
+        let mut s = SyncController::new(48_000, 1);
+        let mut r = Resampler::new(48_000, 48_000, 1);
+        let mut written = 0.0;
+        let mut error_us = 0.0;
+        let buf = [0i32; CHUNK];
+        for k in 0..chunks {
+            // the device has played all but QUEUE frames of what was written
+            let now_s = (written - QUEUE) / (rate * (1.0 + drift_ppm / 1e6));
+            // the device reports its queue at the nominal rate
+            let heard_at_s = now_s + QUEUE / rate;
+            let due_s = (k * CHUNK) as f64 / rate;
+            error_us = (heard_at_s - due_s) * 1e6;
+            assert_eq!(s.update(error_us as i64), SyncAction::Play);
+            r.set_ppm(s.ppm());
+            written += r.process(&buf).len() as f64;
+        }
+        (s, error_us)
+    }
+
+    #[test]
+    fn tracks_a_drifting_dac() {
+        for drift in [-100.0, 60.0] {
+            // ten minutes of audio
+            let (s, error_us) = simulate(drift, 30_000);
+            assert!(error_us.abs() < 300.0, "{drift}ppm: error {error_us}us");
+            // a fast DAC needs the stream slowed down, a slow one sped up
+            assert!((s.ppm() + drift).abs() < 20.0, "{drift}ppm: {}", s.ppm());
+        }
+    }
+}
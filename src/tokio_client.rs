@@ -0,0 +1,186 @@
+use crate::client::{Action, Client, ClientMachine, Event, Message, OwnedMessage, MAX_TRANSMIT};
+use crate::framing::RxBuf;
+use futures_core::Stream;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// Async shell around [`ClientMachine`], the tokio counterpart of
+/// [`crate::client::ConnectedClient`]. Reads are cancel-safe, so Time requests go
+/// out from a timer while waiting for the server instead of needing a read
+/// timeout.
+pub struct AsyncConnectedClient {
+    conn: TcpStream,
+    time_base: Instant,
+    machine: ClientMachine,
+    rx: RxBuf,
+    tx_buf: [u8; MAX_TRANSMIT],
+}
+
+impl Client {
+    pub async fn connect_async<A: ToSocketAddrs>(
+        &self,
+        dst: A,
+    ) -> anyhow::Result<AsyncConnectedClient> {
+        let mut conn = TcpStream::connect(dst).await?;
+        if let Err(e) = conn.set_nodelay(true) {
+            log::error!("Failed to set nodelay on connection: {:?}", e);
+        }
+        let hello = self.hello(conn.peer_addr().ok());
+        conn.write_all(&hello).await?;
+        Ok(AsyncConnectedClient {
+            conn,
+            time_base: Instant::now(),
+            machine: ClientMachine::new(),
+            rx: RxBuf::new(),
+            tx_buf: [0; MAX_TRANSMIT],
+        })
+    }
+}
+
+impl AsyncConnectedClient {
+    fn now_us(&self) -> i64 {
+        self.time_base.elapsed().as_micros() as i64
+    }
+
+    pub fn synchronized(&self) -> bool {
+        self.machine.synchronized()
+    }
+
+    pub fn time_base(&self) -> Instant {
+        self.time_base
+    }
+
+    /// Report a local volume/mute change to the server on the next `recv`.
+    pub fn send_client_info(&mut self, volume: u8, muted: bool) {
+        self.machine.send_client_info(volume, muted);
+    }
+
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        while let Some(n) = self.machine.poll_transmit(self.now_us(), &mut self.tx_buf) {
+            self.conn.write_all(&self.tx_buf[..n]).await?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the next packet from the server, sending Time requests as they
+    /// fall due. Packets the machine consumes itself (Time replies) return
+    /// [`Message::Nothing`].
+    pub async fn recv(&mut self) -> anyhow::Result<Message<'_>> {
+        loop {
+            self.flush().await?;
+            let action = self.machine.next_action();
+            let size = action.size();
+            if self.rx.is_complete(size) {
+                let now = self.now_us();
+                if let Action::ReadPacket(_) = action {
+                    let ev = Event::PacketReceived(self.rx.take(size));
+                    return self.machine.handle_event(ev, now);
+                }
+                let ev = Event::HeaderReceived(self.rx.take(size));
+                // returns Nothing and transitions to ReadPacket; loop to read it
+                self.machine.handle_event(ev, now)?;
+                continue;
+            }
+
+            let due_us = self.machine.next_transmit_us();
+            let wait = Duration::from_micros(due_us.saturating_sub(self.now_us()).max(0) as u64);
+            tokio::select! {
+                n = self.conn.read(self.rx.spare(size)) => {
+                    match n? {
+                        0 => anyhow::bail!("server closed the connection"),
+                        n => self.rx.advance(n),
+                    }
+                }
+                _ = tokio::time::sleep(wait) => (),
+            }
+        }
+    }
+
+    /// Turn the client into a stream of owned messages, ending after the first
+    /// error.
+    pub fn into_stream(self) -> impl Stream<Item = anyhow::Result<OwnedMessage>> {
+        futures_util::stream::unfold(Some(self), |client| async move {
+            let mut client = client?;
+            loop {
+                match client.recv().await {
+                    Ok(msg) => {
+                        if let Some(msg) = msg.into_owned() {
+                            return Some((Ok(msg), Some(client)));
+                        }
+                    }
+                    Err(e) => return Some((Err(e), None)),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{Base, ServerSettings, StreamTags, TimeVal};
+    use crate::server::{ServerSession, SessionOutput};
+    use futures_util::StreamExt;
+    use tokio::net::TcpListener;
+
+    /// Read one client message off `conn`, naming its kind.
+    async fn next_request(conn: &mut TcpStream, s: &mut ServerSession) -> &'static str {
+        let now = TimeVal { sec: 0, usec: 0 };
+        let mut hdr = [0u8; Base::BASE_SIZE];
+        conn.read_exact(&mut hdr).await.unwrap();
+        s.handle_event(Event::HeaderReceived(&hdr), now).unwrap();
+        let Action::ReadPacket(size) = s.next_action() else {
+            unreachable!()
+        };
+        let mut pkt = vec![0; size as usize];
+        conn.read_exact(&mut pkt).await.unwrap();
+        match s.handle_event(Event::PacketReceived(&pkt), now).unwrap() {
+            SessionOutput::Hello(_) => "hello",
+            SessionOutput::TimeRequest { .. } => "time",
+            SessionOutput::ClientInfo(_) => "client info",
+            SessionOutput::None => "none",
+        }
+    }
+
+    #[tokio::test]
+    async fn time_requests_go_out_while_the_server_is_silent() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = ServerSettings {
+            bufferMs: 1000,
+            latency: 0,
+            muted: false,
+            volume: 80,
+        };
+        let tags = StreamTags {
+            title: Some("title".into()),
+            ..Default::default()
+        };
+        let (s, t) = (settings.clone(), tags.clone());
+        let server = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut session = ServerSession::new();
+            assert_eq!(next_request(&mut conn, &mut session).await, "hello");
+            // nothing was sent to the client, so only its timer can trigger this
+            assert_eq!(next_request(&mut conn, &mut session).await, "time");
+            let now = TimeVal { sec: 0, usec: 0 };
+            conn.write_all(&s.as_buf(0, now)).await.unwrap();
+            conn.write_all(&t.as_buf(1, now)).await.unwrap();
+            conn
+        });
+
+        let client = Client::new("00:11:22:33:44:55".into(), "test".into());
+        let stream = client.connect_async(addr).await.unwrap().into_stream();
+        let mut stream = std::pin::pin!(stream);
+        let first = stream.next().await.unwrap().unwrap();
+        assert_eq!(first, OwnedMessage::ServerSettings(settings));
+        let second = stream.next().await.unwrap().unwrap();
+        assert_eq!(second, OwnedMessage::StreamTags(tags));
+
+        // the server hanging up ends the stream with an error
+        drop(server.await.unwrap());
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -0,0 +1,268 @@
+use std::str::FromStr;
+
+/// Gain of 1.0 in the Q15 fixed point used by [`SoftwareGain`]; integer maths
+/// keeps the per-sample cost low on FPU-less embedded targets.
+const UNITY: i32 = 1 << 15;
+
+/// Range covered by [`VolumeCurve::Log`] when the device does not narrow it;
+/// 60dB takes the bottom of the slider to roughly the noise floor of a quiet room.
+const DEFAULT_RANGE_DB: f32 = 60.0;
+
+/// Length of a gain change, in frames. ~10ms at 48kHz: long enough that a jump
+/// from full scale to mute does not click, short enough to feel immediate.
+const RAMP_FRAMES: i32 = 480;
+
+/// How a snapcast volume (0-100) maps onto an output level, shared by the
+/// software gain and the hardware mixers so both sound alike.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VolumeCurve {
+    /// Amplitude proportional to the volume. Most of the audible change is
+    /// crammed into the bottom of the slider.
+    Linear,
+    /// Equal dB steps across the device's range (or [`DEFAULT_RANGE_DB`] below
+    /// full scale), which roughly follows perceived loudness.
+    #[default]
+    Log,
+    /// Like [`VolumeCurve::Log`], over an explicit dB range. Levels the device
+    /// cannot reach are clamped to its range.
+    Db { min_db: f32, max_db: f32 },
+}
+
+impl VolumeCurve {
+    /// Level in dB for `volume` on a device spanning `[min_db, max_db]`, or
+    /// `None` for volume 0, which is always silence.
+    pub fn db(&self, volume: u8, min_db: f32, max_db: f32) -> Option<f32> {
+        let v = volume.min(100) as f32 / 100.0;
+        if v == 0.0 {
+            return None;
+        }
+        let db = match *self {
+            VolumeCurve::Linear => max_db + 20.0 * v.log10(),
+            VolumeCurve::Log => min_db + (max_db - min_db) * v,
+            VolumeCurve::Db {
+                min_db: lo,
+                max_db: hi,
+            } => lo + (hi - lo) * v,
+        };
+        Some(db.min(max_db).max(min_db))
+    }
+
+    /// Amplitude factor in `0.0..=1.0` for `volume`, with full scale as 0dB.
+    pub fn gain(&self, volume: u8) -> f32 {
+        match self.db(volume, -DEFAULT_RANGE_DB, 0.0) {
+            None => 0.0,
+            Some(db) => 10f32.powf(db / 20.0),
+        }
+    }
+}
+
+impl FromStr for VolumeCurve {
+    type Err = String;
+    /// `linear`, `log`, or `db:<min>:<max>` (e.g. `db:-50:0`).
+    fn from_str(s: &str) -> Result<VolumeCurve, String> {
+        match s {
+            "linear" => return Ok(VolumeCurve::Linear),
+            "log" => return Ok(VolumeCurve::Log),
+            _ => {}
+        }
+        let bad = || format!("invalid volume curve {s:?}: expected linear, log or db:<min>:<max>");
+        let range = s.strip_prefix("db:").ok_or_else(bad)?;
+        let (min, max) = range.split_once(':').ok_or_else(bad)?;
+        let min_db: f32 = min.parse().map_err(|_| bad())?;
+        let max_db: f32 = max.parse().map_err(|_| bad())?;
+        if min_db >= max_db {
+            return Err(format!("volume curve range {min_db}..{max_db}dB is empty"));
+        }
+        Ok(VolumeCurve::Db { min_db, max_db })
+    }
+}
+
+/// Software volume applied to decoded samples right before they reach the
+/// player, so `ServerSettings` volume and mute work on every backend. Gain
+/// changes are ramped linearly over [`RAMP_FRAMES`] instead of applied as a step.
+pub struct SoftwareGain {
+    curve: VolumeCurve,
+    channels: usize,
+    current: i32,
+    target: i32,
+    step: i32,
+}
+
+impl Default for SoftwareGain {
+    fn default() -> SoftwareGain {
+        SoftwareGain::new(2)
+    }
+}
+
+impl SoftwareGain {
+    pub fn new(channels: usize) -> SoftwareGain {
+        SoftwareGain {
+            curve: VolumeCurve::default(),
+            channels: channels.max(1),
+            current: UNITY,
+            target: UNITY,
+            step: 0,
+        }
+    }
+
+    /// The ramp advances once per frame, so every channel of a frame gets the
+    /// same gain; keep this in sync with the stream's channel count.
+    pub fn set_channels(&mut self, channels: usize) {
+        self.channels = channels.max(1);
+    }
+
+    pub fn set_curve(&mut self, curve: VolumeCurve) {
+        self.curve = curve;
+    }
+
+    pub fn set(&mut self, volume: u8, muted: bool) {
+        let target = if muted {
+            0
+        } else {
+            (self.curve.gain(volume) * UNITY as f32).round() as i32
+        };
+        self.set_gain(target);
+    }
+
+    fn set_gain(&mut self, target: i32) {
+        self.target = target;
+        self.step = ((target - self.current).abs() / RAMP_FRAMES).max(1);
+    }
+
+    pub fn apply(&mut self, buf: &mut [i32]) {
+        if self.current == self.target {
+            match self.current {
+                UNITY => {}
+                0 => buf.fill(0),
+                g => buf.iter_mut().for_each(|s| *s = scale(*s, g)),
+            }
+            return;
+        }
+        for frame in buf.chunks_mut(self.channels) {
+            if self.current < self.target {
+                self.current = (self.current + self.step).min(self.target);
+            } else if self.current > self.target {
+                self.current = (self.current - self.step).max(self.target);
+            }
+            for s in frame.iter_mut() {
+                *s = scale(*s, self.current);
+            }
+        }
+    }
+}
+
+fn scale(s: i32, gain: i32) -> i32 {
+    // gain <= UNITY, so the product always fits back into an i32
+    ((s as i64 * gain as i64) >> 15) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curve_endpoints() {
+        for curve in [
+            VolumeCurve::Linear,
+            VolumeCurve::Log,
+            "db:-40:0".parse().unwrap(),
+        ] {
+            assert_eq!(curve.gain(0), 0.0);
+            assert_eq!(curve.gain(100), 1.0);
+            assert!(curve.gain(50) <= 0.5);
+            // out-of-range volumes clamp instead of amplifying
+            assert_eq!(curve.gain(255), 1.0);
+        }
+    }
+
+    fn approx(got: Option<f32>, want: f32) {
+        let got = got.expect("expected a level, got silence");
+        assert!((got - want).abs() < 1e-3, "{got} != {want}");
+    }
+
+    #[test]
+    fn curves_map_onto_device_range() {
+        // a device spanning -100..+6dB
+        approx(VolumeCurve::Log.db(50, -100.0, 6.0), -47.0);
+        approx(VolumeCurve::Linear.db(10, -100.0, 6.0), -14.0);
+        // linear never goes below what the device can do
+        approx(VolumeCurve::Linear.db(1, -20.0, 0.0), -20.0);
+        // a custom range keeps its own steps and is clamped to the device
+        let c = VolumeCurve::Db {
+            min_db: -80.0,
+            max_db: 10.0,
+        };
+        approx(c.db(100, -60.0, 0.0), 0.0);
+        approx(c.db(50, -60.0, 0.0), -35.0);
+        approx(c.db(10, -60.0, 0.0), -60.0);
+        assert_eq!(c.db(0, -60.0, 0.0), None);
+        // ...even when it lies entirely outside the device's range
+        let c = VolumeCurve::Db {
+            min_db: -20.0,
+            max_db: 0.0,
+        };
+        approx(c.db(100, -100.0, -30.0), -30.0);
+        approx(c.db(1, -100.0, -30.0), -30.0);
+    }
+
+    #[test]
+    fn parse_curves() {
+        assert_eq!("linear".parse(), Ok(VolumeCurve::Linear));
+        assert_eq!("log".parse(), Ok(VolumeCurve::Log));
+        assert_eq!(
+            "db:-50.5:0".parse(),
+            Ok(VolumeCurve::Db {
+                min_db: -50.5,
+                max_db: 0.0
+            })
+        );
+        assert!("db:0:-50".parse::<VolumeCurve>().is_err());
+        assert!("db:-50".parse::<VolumeCurve>().is_err());
+        assert!("cubic".parse::<VolumeCurve>().is_err());
+    }
+
+    #[test]
+    fn unity_is_bit_exact() {
+        let mut g = SoftwareGain::new(2);
+        let orig: Vec<i32> = (0..64).map(|i| (i * 997 - 30_000) << 16).collect();
+        let mut buf = orig.clone();
+        g.apply(&mut buf);
+        assert_eq!(buf, orig);
+    }
+
+    #[test]
+    fn mute_ramps_down_without_a_step() {
+        let mut g = SoftwareGain::new(2);
+        g.set(100, true);
+        let mut buf = vec![i32::MAX; (RAMP_FRAMES as usize + 10) * 2];
+        g.apply(&mut buf);
+
+        // the first frame is barely attenuated; the level never jumps up and
+        // both channels of a frame share one gain
+        assert!(buf[0] > i32::MAX - (200 << 16));
+        for f in buf.chunks(2) {
+            assert_eq!(f[0], f[1]);
+        }
+        for w in buf.chunks(2).collect::<Vec<_>>().windows(2) {
+            assert!(w[1][0] <= w[0][0]);
+        }
+        assert_eq!(*buf.last().unwrap(), 0);
+
+        // once the ramp finished, later buffers are fully silent
+        let mut buf = vec![1234i32; 16];
+        g.apply(&mut buf);
+        assert!(buf.iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn unmute_ramps_back_to_volume() {
+        let mut g = SoftwareGain::new(1);
+        g.set(100, true);
+        g.apply(&mut vec![0; RAMP_FRAMES as usize * 2]);
+        g.set(100, false);
+        let mut buf = vec![10_000i32; RAMP_FRAMES as usize * 2];
+        g.apply(&mut buf);
+        assert!(buf[0] < 100);
+        assert_eq!(*buf.last().unwrap(), 10_000);
+    }
+}